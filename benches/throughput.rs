@@ -0,0 +1,75 @@
+//! Filtering throughput on synthetic read pairs, for comparing performance across versions. Run
+//! with `cargo bench`, optionally setting BENCH_READ_PAIRS to change the input size.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use std::time::Instant;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rand::{Rng,SeedableRng};
+use rand::rngs::StdRng;
+use structopt::StructOpt;
+use rustq_filterer::{Cli,filter};
+
+
+const READ_LEN: usize = 150;
+
+
+/// Write a gzipped fastq of random reads with Illumina-style headers
+fn write_reads(file_path: &Path, mate: u8, n_pairs: usize, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut f = GzEncoder::new(File::create(file_path).unwrap(), Compression::fast());
+    for i in 0..n_pairs {
+        let len = rng.gen_range(READ_LEN / 4..=READ_LEN);
+        let seq: String = (0..len).map(|_| b"ACGTN"[rng.gen_range(0..5)] as char).collect();
+        let qual: String = (0..len).map(|_| (b'!' + rng.gen_range(2..41)) as char).collect();
+        write!(
+            f, "@BENCH:1:FC:1:{}:{}:{} {}:N:0:ACGT\n{}\n+\n{}\n",
+            1101 + i % 16, i % 30000, i / 30000, mate, seq, qual
+        ).unwrap();
+    }
+    f.finish().unwrap();
+}
+
+
+fn run(name: &str, inputs: &(PathBuf, PathBuf), out_dir: &Path, n_pairs: usize, extra_args: &[&str]) {
+    let mut args: Vec<String> = vec!["rustq_filterer".to_string()];
+    let files = [
+        ("--i1", inputs.0.clone()), ("--i2", inputs.1.clone()),
+        ("--o1", out_dir.join("o1.fastq")), ("--o2", out_dir.join("o2.fastq")),
+        ("--f1", out_dir.join("f1.fastq")), ("--f2", out_dir.join("f2.fastq")),
+        ("--stats_file", out_dir.join("stats.txt"))
+    ];
+    for (flag, path) in files.iter() {
+        args.push(flag.to_string());
+        args.push(path.to_str().unwrap().to_string());
+    }
+    args.extend(extra_args.iter().map(|a| a.to_string()));
+
+    let cli = Cli::from_iter(args);
+    let start = Instant::now();
+    filter(&cli).expect("Benchmark run failed");
+    let secs = start.elapsed().as_secs_f64();
+    println!("{:<16} {:>8.3}s {:>12.0} pairs/s", name, secs, n_pairs as f64 / secs);
+}
+
+
+fn main() {
+    let n_pairs = std::env::var("BENCH_READ_PAIRS").ok().and_then(|n| n.parse().ok()).unwrap_or(200_000);
+    let dir = std::env::temp_dir().join(format!("rustq_filterer_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let inputs = (dir.join("bench_R1.fastq.gz"), dir.join("bench_R2.fastq.gz"));
+    write_reads(&inputs.0, 1, n_pairs, 1);
+    write_reads(&inputs.1, 2, n_pairs, 2);
+
+    println!("{} read pairs of up to {}bp", n_pairs, READ_LEN);
+    run("passthrough", &inputs, &dir, n_pairs, &["--passthrough"]);
+    run("length", &inputs, &dir, n_pairs, &[]);
+    run("tiles_quality", &inputs, &dir, n_pairs, &["--remove_tiles", "1101", "--min_q30_frac", "0.5", "--max_ambiguous_frac", "0.1"]);
+    run("contaminants", &inputs, &dir, n_pairs, &["--contaminant_seq", "AGATCGGAAGAGC", "--contaminant_mismatches", "1"]);
+    run("transforms", &inputs, &dir, n_pairs, &["--trim_trailing_n", "--bin_quality", "--strip_header_comment"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
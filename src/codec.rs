@@ -0,0 +1,400 @@
+use std::fs::{File,OpenOptions};
+use std::io::{self,BufRead,BufReader,BufWriter,Read,Write};
+use std::path::Path;
+use std::sync::RwLock;
+use std::sync::mpsc::{self,Receiver};
+use std::thread::{self,JoinHandle};
+#[cfg(feature="gzip")]
+use flate2::Compression;
+#[cfg(feature="gzip")]
+use flate2::read::MultiGzDecoder;
+#[cfg(feature="gzip")]
+use flate2::write::GzEncoder;
+#[cfg(feature="gzip")]
+use crate::error::GzipStreamError;
+use crate::error::MissingCodecError;
+use crate::remote;
+
+
+/// Wraps a raw input stream in a decompression layer
+pub type Decoder = fn(Box<dyn Read>) -> Box<dyn BufRead>;
+
+/// Wraps a raw output stream in a compression layer at a given level, 0-9
+pub type Encoder = fn(Box<dyn Write>, u32) -> Box<dyn Output>;
+
+
+/// Decoders by file extension. Inputs with any other extension, e.g. .fastq, use DEFAULT_DECODER.
+/// Each codec is behind a Cargo feature of the same name, so that lean builds can leave it out
+const DECODERS: &[(&str, Decoder)] = &[
+    #[cfg(feature="gzip")]
+    ("gz", gzip_decoder)
+];
+const DEFAULT_DECODER: Decoder = plain_decoder;
+
+/// Encoders by file extension. Outputs with any other extension use DEFAULT_ENCODER
+const ENCODERS: &[(&str, Encoder)] = &[
+    #[cfg(feature="gzip")]
+    ("gz", gzip_encoder)
+];
+const DEFAULT_ENCODER: Encoder = plain_encoder;
+
+/// Codecs registered at runtime by library users, by extension. These are looked up before the
+/// built-in ones, so can replace them
+static REGISTERED: RwLock<Vec<(String, Decoder, Encoder)>> = RwLock::new(Vec::new());
+
+/// Every codec extension and the feature it's behind, whether or not it's in this build, so that
+/// files needing a missing codec are refused rather than treated as plain text
+const FEATURE_CODECS: &[(&str, &str)] = &[("gz", "gzip")];
+
+/// Bytes of decoded input handed over by a background reader at a time, and how many of these it
+/// can get ahead by
+const BACKGROUND_BLOCK_SIZE: usize = 1 << 20;
+const BACKGROUND_BLOCKS: usize = 4;
+
+/// Input or output path standing for stdin or stdout
+pub const STDIO: &str = "-";
+
+/// Added to default output names, so that they're compressed when the build supports it
+#[cfg(feature="gzip")]
+pub const OUTPUT_SUFFIX: &str = ".gz";
+#[cfg(not(feature="gzip"))]
+pub const OUTPUT_SUFFIX: &str = "";
+
+
+/// An output stream that has to be finished, e.g. to write a gzip trailer. Dropping one instead
+/// would lose any error from the last writes. Encoders passed to `register` return one
+pub trait Output: Write {
+    /// Write out anything buffered and end the stream
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+
+impl<W: Write> Output for BufWriter<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+
+#[cfg(feature="gzip")]
+impl<W: Write> Output for GzEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        GzEncoder::finish(*self)?.flush()
+    }
+}
+
+
+impl Output for io::Sink {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+/// Finish an output, leaving a sink in its place
+pub fn close(output: &mut Box<dyn Output>) -> io::Result<()> {
+    std::mem::replace(output, Box::new(io::sink())).finish()
+}
+
+
+/// Gzip decoder that tags the errors it raises as GzipStreamError, so a corrupt or truncated stream
+/// can be reported as such rather than as a generic read failure.
+#[cfg(feature="gzip")]
+struct GzipReader<R: Read> {
+    decoder: MultiGzDecoder<R>
+}
+
+
+#[cfg(feature="gzip")]
+impl<R: Read> GzipReader<R> {
+    fn new(inner: R) -> GzipReader<R> {
+        GzipReader { decoder: MultiGzDecoder::new(inner) }
+    }
+}
+
+
+#[cfg(feature="gzip")]
+impl<R: Read> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf).map_err(
+            |e| match e.kind() {
+                io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                    io::Error::new(e.kind(), GzipStreamError(e))
+                },
+                _ => e
+            }
+        )
+    }
+}
+
+
+#[cfg(feature="gzip")]
+fn gzip_decoder(inner: Box<dyn Read>) -> Box<dyn BufRead> {
+    Box::new(BufReader::new(GzipReader::new(inner)))
+}
+
+
+fn plain_decoder(inner: Box<dyn Read>) -> Box<dyn BufRead> {
+    Box::new(BufReader::new(inner))
+}
+
+
+#[cfg(feature="gzip")]
+fn gzip_encoder(inner: Box<dyn Write>, level: u32) -> Box<dyn Output> {
+    Box::new(GzEncoder::new(BufWriter::new(inner), Compression::new(level)))
+}
+
+
+fn plain_encoder(inner: Box<dyn Write>, _level: u32) -> Box<dyn Output> {
+    Box::new(BufWriter::new(inner))
+}
+
+
+/// Input opened and decoded on a background thread, which passes it over in blocks
+struct BackgroundReader {
+    blocks: Receiver<io::Result<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    block: Vec<u8>,
+    pos: usize
+}
+
+
+impl BackgroundReader {
+    /// Read and pass over blocks until the input ends, fails, or the BackgroundReader is dropped
+    fn decode(mut reader: Box<dyn BufRead>, blocks: mpsc::SyncSender<io::Result<Vec<u8>>>) {
+        loop {
+            let mut block = Vec::with_capacity(BACKGROUND_BLOCK_SIZE);
+            let result = (&mut reader).take(BACKGROUND_BLOCK_SIZE as u64).read_to_end(&mut block);
+            // anything read before an error is still passed over, so that errors are reported
+            // against the right record
+            if !block.is_empty() && blocks.send(Ok(block)).is_err() {
+                return;
+            }
+            match result {
+                Ok(0) => return,
+                Ok(_) => {},
+                Err(e) => {
+                    let _ = blocks.send(Err(e));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+
+impl Read for BackgroundReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+
+impl BufRead for BackgroundReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.block.len() {
+            match self.blocks.recv() {
+                Ok(block) => {
+                    self.block = block?;
+                    self.pos = 0;
+                },
+                Err(_) => {
+                    // the thread has hung up, which is the end of the input unless it panicked
+                    if let Some(thread) = self.thread.take() {
+                        if thread.join().is_err() {
+                            return Err(io::Error::other("Background reader panicked"));
+                        }
+                    }
+                    return Ok(&[]);
+                }
+            }
+        }
+        Ok(&self.block[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.block.len());
+    }
+}
+
+
+fn extension(file_path: &Path) -> Option<&str> {
+    file_path.extension().and_then(|e| e.to_str())
+}
+
+
+/// Whether a path is "-", for stdin as an input or stdout as an output
+pub fn is_stdio(file_path: &Path) -> bool {
+    file_path == Path::new(STDIO)
+}
+
+
+/// Read and write files with extension `ext`, e.g. "zst" for sample.fastq.zst, through a custom
+/// decoder and encoder. Applies to every input and output opened afterwards, and replaces any
+/// codec already used for `ext`, including built-in ones
+pub fn register(ext: &str, decoder: Decoder, encoder: Encoder) {
+    let mut registered = REGISTERED.write().unwrap_or_else(|e| e.into_inner());
+    registered.retain(|(e, _, _)| e != ext);
+    registered.push((ext.to_string(), decoder, encoder));
+}
+
+
+/// The registered codec for an extension, if any
+fn registered(ext: Option<&str>) -> Option<(Decoder, Encoder)> {
+    let registered = REGISTERED.read().unwrap_or_else(|e| e.into_inner());
+    registered.iter().find(|(e, _, _)| Some(e.as_str()) == ext).map(|(_, decoder, encoder)| (*decoder, *encoder))
+}
+
+
+/// Whether there's a codec for an extension, e.g. "gz" only with the gzip feature unless one was
+/// registered for it
+pub fn supports(ext: &str) -> bool {
+    registered(Some(ext)).is_some() || DECODERS.iter().any(|(e, _)| *e == ext)
+}
+
+
+/// Fail with a MissingCodecError if `ext` is for a codec that this build doesn't include
+fn check_compiled_in(file_path: &Path, ext: Option<&str>) -> io::Result<()> {
+    match FEATURE_CODECS.iter().find(|(e, _)| Some(*e) == ext) {
+        Some((e, feature)) if !supports(e) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            MissingCodecError { file: file_path.to_path_buf(), feature }
+        )),
+        _ => Ok(())
+    }
+}
+
+
+fn decoder_for_ext(file_path: &Path, ext: Option<&str>) -> io::Result<Decoder> {
+    if let Some((decoder, _)) = registered(ext) {
+        return Ok(decoder);
+    }
+    check_compiled_in(file_path, ext)?;
+    Ok(DECODERS.iter().find(|(e, _)| Some(*e) == ext).map_or(DEFAULT_DECODER, |(_, decoder)| *decoder))
+}
+
+
+fn encoder_for_ext(file_path: &Path, ext: Option<&str>) -> io::Result<Encoder> {
+    if let Some((_, encoder)) = registered(ext) {
+        return Ok(encoder);
+    }
+    check_compiled_in(file_path, ext)?;
+    Ok(ENCODERS.iter().find(|(e, _)| Some(*e) == ext).map_or(DEFAULT_ENCODER, |(_, encoder)| *encoder))
+}
+
+
+pub fn decoder_for(file_path: &Path) -> io::Result<Decoder> {
+    decoder_for_ext(file_path, extension(file_path))
+}
+
+
+pub fn encoder_for(file_path: &Path) -> io::Result<Encoder> {
+    encoder_for_ext(file_path, extension(file_path))
+}
+
+
+/// Add the path being opened to an error, which otherwise wouldn't say which file it was about
+fn open_error(e: io::Error, file_path: &Path) -> io::Error {
+    io::Error::new(e.kind(), format!("Could not open {:?}: {}", file_path, e))
+}
+
+
+/// Open a local file, an http(s):// or s3:// URL if built with the 'remote' feature, or stdin for
+/// "-", with the decoder for its extension
+pub fn open_reader(input_file: &Path) -> io::Result<Box<dyn BufRead>> {
+    open_reader_as(input_file, None)
+}
+
+
+/// As open_reader, but decoding stdin as if it had the extension `stdin_ext`, since it has no
+/// extension of its own to go by
+pub fn open_reader_as(input_file: &Path, stdin_ext: Option<&str>) -> io::Result<Box<dyn BufRead>> {
+    if is_stdio(input_file) {
+        // not locked, as an interleaved input is opened by both mates' handlers
+        return Ok(decoder_for_ext(input_file, stdin_ext)?(Box::new(io::stdin())));
+    }
+    let decoder = decoder_for(input_file)?;
+    let raw: Box<dyn Read> = match input_file.to_str() {
+        Some(url) if remote::is_remote(url) => remote::open(url)?,
+        _ => Box::new(File::open(input_file).map_err(|e| open_error(e, input_file))?)
+    };
+    Ok(decoder(raw))
+}
+
+
+/// As open_reader_as, but opening and decoding the input on a background thread, so that
+/// decompression runs alongside whatever reads from it
+pub fn open_background_reader(input_file: &Path, stdin_ext: Option<&'static str>) -> io::Result<Box<dyn BufRead>> {
+    let (opened_tx, opened_rx) = mpsc::channel();
+    let (blocks_tx, blocks_rx) = mpsc::sync_channel(BACKGROUND_BLOCKS);
+    let input_file = input_file.to_path_buf();
+    let thread = thread::spawn(move || {
+        match open_reader_as(&input_file, stdin_ext) {
+            Ok(reader) => {
+                let _ = opened_tx.send(Ok(()));
+                BackgroundReader::decode(reader, blocks_tx);
+            },
+            Err(e) => {
+                let _ = opened_tx.send(Err(e));
+            }
+        }
+    });
+    opened_rx.recv().unwrap_or_else(
+        |_| Err(io::Error::other("Background reader stopped before opening its input"))
+    )?;
+    Ok(Box::new(BackgroundReader { blocks: blocks_rx, thread: Some(thread), block: Vec::new(), pos: 0 }))
+}
+
+
+/// Create or append to an output file, or write to stdout for "-", with the encoder for its
+/// extension. Appending to a compressed file adds a new gzip member, which readers treat as a
+/// continuation of the file
+pub fn open_writer(output_file: &Path, append: bool, level: u32) -> io::Result<Box<dyn Output>> {
+    open_writer_as(output_file, append, level, None)
+}
+
+
+/// As open_writer, but encoding stdout as if it had the extension `stdout_ext`
+pub fn open_writer_as(
+    output_file: &Path, append: bool, level: u32, stdout_ext: Option<&str>
+) -> io::Result<Box<dyn Output>> {
+    if is_stdio(output_file) {
+        return Ok(encoder_for_ext(output_file, stdout_ext)?(Box::new(io::stdout().lock()), level));
+    }
+    let encoder = encoder_for(output_file)?;
+    let f = if append {
+        OpenOptions::new().append(true).create(true).open(output_file)
+    } else {
+        File::create(output_file)
+    };
+    let f = f.map_err(|e| open_error(e, output_file))?;
+    Ok(encoder(Box::new(f), level))
+}
+
+
+#[cfg(all(test, feature="gzip"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_member_of_concatenated_gzip() {
+        let file_path = std::env::temp_dir().join(format!("rustq_filterer_test_multi_member_{}.fastq.gz", std::process::id()));
+        let records = ["@r1\nACGT\n+\nIIII\n", "@r2\nTTGA\n+\nIIII\n"];
+        let mut members = Vec::new();
+        for record in &records {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(record.as_bytes()).unwrap();
+            members.extend(encoder.finish().unwrap());
+        }
+        std::fs::write(&file_path, members).unwrap();
+
+        let mut content = String::new();
+        open_reader(&file_path).unwrap().read_to_string(&mut content).unwrap();
+        std::fs::remove_file(&file_path).unwrap();
+        assert_eq!(content, records.concat());
+    }
+}
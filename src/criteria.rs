@@ -0,0 +1,762 @@
+use std::cell::Cell;
+use std::collections::{HashMap,HashSet};
+use std::fs::File;
+use std::io::{self,BufRead,BufReader};
+use std::path::Path;
+use aho_corasick::{AhoCorasick,AhoCorasickBuilder};
+use log::debug;
+use crate::error::{FiltererError,Result};
+use crate::{FastqEntry,hard_trimmed,mate_number,normalise_tile};
+
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum CheckResult {
+    Pass,
+    Fail
+}
+
+
+impl From<bool> for CheckResult {
+    fn from(passed: bool) -> CheckResult {
+        if passed { CheckResult::Pass } else { CheckResult::Fail }
+    }
+}
+
+
+/// A filtering criterion, carrying its own parameters and counters. Criteria only see the current
+/// pair of records, so they can't depend on any other state of the run.
+pub trait Criterion {
+    /// Name used to report failures, e.g. in the annotation file
+    fn name(&self) -> &'static str;
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult;
+
+    /// Counters to report in the stats file
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        Vec::new()
+    }
+}
+
+
+/// A per-pair predicate, for library users to add their own checks alongside the built-in
+/// criteria. Every criterion is also a PairFilter
+pub trait PairFilter {
+    /// Name used to report failures, e.g. in the annotation file
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    fn keep(&self, r1: &FastqEntry, r2: &FastqEntry) -> bool;
+}
+
+
+impl<C: Criterion> PairFilter for C {
+    fn name(&self) -> &'static str {
+        Criterion::name(self)
+    }
+
+    fn keep(&self, r1: &FastqEntry, r2: &FastqEntry) -> bool {
+        self.evaluate(r1, r2) == CheckResult::Pass
+    }
+}
+
+
+fn increment(counter: &Cell<i64>) {
+    counter.set(counter.get() + 1);
+}
+
+
+/// Counts of pairs failing a bound through R1, R2 or both mates
+#[derive(Default)]
+struct MateCounters {
+    r1: Cell<i64>,
+    r2: Cell<i64>,
+    both: Cell<i64>
+}
+
+
+impl MateCounters {
+    /// Count a pair by which of its mates failed, returning whether either did
+    fn record(&self, r1_failed: bool, r2_failed: bool) -> bool {
+        let counter = match (r1_failed, r2_failed) {
+            (true, true) => &self.both,
+            (true, false) => &self.r1,
+            (false, true) => &self.r2,
+            (false, false) => return false
+        };
+        increment(counter);
+        true
+    }
+}
+
+
+pub struct Length {
+    thresholds: (usize, usize),
+    max_length: Option<usize>,
+    single_end: bool,
+    too_short: MateCounters,
+    too_long: MateCounters
+}
+
+
+impl Length {
+    /// Pairs fail if either mate is shorter than its own threshold in `thresholds`, or longer than
+    /// `max_length`. With `single_end`, the read is passed as both mates and only counted as R1
+    pub fn new(thresholds: (usize, usize), max_length: Option<usize>, single_end: bool) -> Length {
+        Length { thresholds, max_length, single_end, too_short: MateCounters::default(), too_long: MateCounters::default() }
+    }
+}
+
+
+impl Criterion for Length {
+    fn name(&self) -> &'static str { "length" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        let (r1_len, r2_len) = (r1.seq_len(), r2.seq_len());
+        let paired = !self.single_end;
+        let (threshold_1, threshold_2) = self.thresholds;
+        let too_short = self.too_short.record(r1_len < threshold_1, paired && r2_len < threshold_2);
+        let too_long = match self.max_length {
+            Some(max_length) => self.too_long.record(r1_len > max_length, paired && r2_len > max_length),
+            None => false
+        };
+        (!too_short && !too_long).into()
+    }
+
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        vec![
+            ("r1_too_short", self.too_short.r1.get()),
+            ("r2_too_short", self.too_short.r2.get()),
+            ("both_too_short", self.too_short.both.get()),
+            ("r1_too_long", self.too_long.r1.get()),
+            ("r2_too_long", self.too_long.r2.get()),
+            ("both_too_long", self.too_long.both.get())
+        ]
+    }
+}
+
+
+/// Tiles to remove, or with `keep` the only tiles to keep
+pub struct Tiles {
+    tiles: HashSet<String>,
+    keep: bool
+}
+
+
+impl Tiles {
+    pub fn new(input_tiles: &[String], keep: bool) -> Tiles {
+        debug!("{} tiles: {:?}", if keep { "Keeping" } else { "Removing" }, input_tiles);
+        let tiles = input_tiles.iter().map(|t| normalise_tile(t)).filter(|t| !t.is_empty()).collect();
+        Tiles { tiles, keep }
+    }
+
+    /// Tile IDs from a file, one per line
+    pub fn read_file(input_tiles: &Path) -> Result<Vec<String>> {
+        debug!("Reading tiles from {:?}", input_tiles);
+        let f = BufReader::new(File::open(input_tiles)?);
+        let mut tiles = Vec::new();
+        for line in f.lines() {
+            let line = line?;
+            let line = clean_list_line(&line);
+            if !line.is_empty() && !line.starts_with('#') {
+                tiles.push(line.to_string());
+            }
+        }
+        Ok(tiles)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+
+impl Criterion for Tiles {
+    fn name(&self) -> &'static str {
+        if self.keep { "keep_tile" } else { "tile" }
+    }
+
+    fn evaluate(&self, r1: &FastqEntry, _r2: &FastqEntry) -> CheckResult {
+        if self.keep {
+            return self.tiles.contains(&r1.tile_id).into();
+        }
+        // pairs without a tile ID can't be matched against the list, so are kept
+        (r1.tile_id.is_empty() || !self.tiles.contains(&r1.tile_id)).into()
+    }
+}
+
+
+/// Pairs where either header has no tile ID to check
+#[derive(Default)]
+pub struct UnparseableTiles {
+    removed: Cell<i64>
+}
+
+
+impl Criterion for UnparseableTiles {
+    fn name(&self) -> &'static str { "unparseable_tile" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        let unparseable = r1.tile_id.is_empty() || r2.tile_id.is_empty();
+        if unparseable {
+            increment(&self.removed);
+        }
+        (!unparseable).into()
+    }
+
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        vec![("unparseable_tile_pairs_removed", self.removed.get())]
+    }
+}
+
+
+/// Strip the byte order mark and surrounding whitespace that list files exported from
+/// spreadsheets can carry, and which would otherwise stop entries from ever matching
+fn clean_list_line(line: &str) -> &str {
+    line.trim_start_matches('\u{feff}').trim()
+}
+
+
+/// Read IDs from a file, either removed from the output or, with `keep`, the only ones kept
+pub struct ReadIds {
+    read_ids: HashSet<String>,
+    keep: bool
+}
+
+
+impl ReadIds {
+    pub fn from_file(input_reads: &Path, keep: bool) -> Result<ReadIds> {
+        debug!("{} reads in {:?}", if keep { "Extracting" } else { "Removing" }, input_reads);
+        let f = BufReader::new(File::open(input_reads)?);
+        let mut read_ids = HashSet::new();
+        for line in f.lines() {
+            let line = line?;
+            let line = clean_list_line(&line);
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let read_id = format!("@{}", line.split_whitespace().next().unwrap());
+            read_ids.insert(read_id);
+        }
+        Ok(ReadIds { read_ids, keep })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read_ids.is_empty()
+    }
+}
+
+
+impl Criterion for ReadIds {
+    fn name(&self) -> &'static str {
+        if self.keep { "extract" } else { "id" }
+    }
+
+    fn evaluate(&self, r1: &FastqEntry, _r2: &FastqEntry) -> CheckResult {
+        (self.read_ids.contains(&r1.read_id) == self.keep).into()
+    }
+}
+
+
+/// Read IDs starting with any of a set of prefixes, given with or without the leading '@'
+pub struct IdPrefixes {
+    prefixes: Vec<String>,
+    removed: Cell<i64>
+}
+
+
+impl IdPrefixes {
+    pub fn new(prefixes: &[String]) -> IdPrefixes {
+        debug!("Removing reads with ID prefixes: {:?}", prefixes);
+        let prefixes = prefixes.iter().map(|p| format!("@{}", p.trim_start_matches('@'))).collect();
+        IdPrefixes { prefixes, removed: Cell::new(0) }
+    }
+}
+
+
+impl Criterion for IdPrefixes {
+    fn name(&self) -> &'static str { "id_prefix" }
+
+    fn evaluate(&self, r1: &FastqEntry, _r2: &FastqEntry) -> CheckResult {
+        let matched = self.prefixes.iter().any(|p| r1.read_id.starts_with(p.as_str()));
+        if matched {
+            increment(&self.removed);
+        }
+        (!matched).into()
+    }
+
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        vec![("id_prefix_pairs_removed", self.removed.get())]
+    }
+}
+
+
+/// A rectangle of flowcell coordinates within a tile, bounds inclusive
+struct Region {
+    x_min: u32,
+    y_min: u32,
+    x_max: u32,
+    y_max: u32
+}
+
+
+impl Region {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x_min && x <= self.x_max && y >= self.y_min && y <= self.y_max
+    }
+}
+
+
+pub struct Regions {
+    regions: HashMap<String, Vec<Region>>,
+    removed: Cell<i64>
+}
+
+
+impl Regions {
+    pub fn from_file(input_regions: &Path) -> Result<Regions> {
+        debug!("Removing regions in {:?}", input_regions);
+        let f = BufReader::new(File::open(input_regions)?);
+        let mut regions: HashMap<String, Vec<Region>> = HashMap::new();
+        for (i, line) in f.lines().enumerate() {
+            let line = line?;
+            let line = clean_list_line(&line);
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let coords: Vec<u32> = fields.iter().skip(1).filter_map(|c| c.parse().ok()).collect();
+            if fields.len() != 5 || coords.len() != 4 {
+                return Err(FiltererError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Expected 'tile x_min y_min x_max y_max' on line {} of {:?}", i + 1, input_regions)
+                )));
+            }
+
+            regions.entry(normalise_tile(fields[0])).or_default().push(
+                Region { x_min: coords[0], y_min: coords[1], x_max: coords[2], y_max: coords[3] }
+            );
+        }
+        Ok(Regions { regions, removed: Cell::new(0) })
+    }
+}
+
+
+impl Criterion for Regions {
+    fn name(&self) -> &'static str { "region" }
+
+    fn evaluate(&self, r1: &FastqEntry, _r2: &FastqEntry) -> CheckResult {
+        let in_region = match (self.regions.get(&r1.tile_id), r1.x, r1.y) {
+            (Some(regions), Some(x), Some(y)) => regions.iter().any(|r| r.contains(x, y)),
+            _ => false
+        };
+        if in_region {
+            increment(&self.removed);
+        }
+        (!in_region).into()
+    }
+
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        vec![("region_pairs_removed", self.removed.get())]
+    }
+}
+
+
+pub struct Contaminants {
+    contaminants: Vec<Vec<u8>>,
+    matcher: Option<AhoCorasick>,
+    mismatches: usize,
+    removed: Cell<i64>
+}
+
+
+impl Contaminants {
+    /// Refuses empty motifs, and mismatch budgets that would let a motif match anywhere
+    pub fn new(seqs: &[String], mismatches: usize) -> Result<Contaminants> {
+        debug!("Removing contaminants: {:?}", seqs);
+        for seq in seqs {
+            if seq.is_empty() {
+                return Err(FiltererError::Config("--contaminant_seq can't be empty".to_string()));
+            }
+            if mismatches >= seq.len() {
+                return Err(FiltererError::Config(format!(
+                    "--contaminant_mismatches {} would let '{}' ({}bp) match any read", mismatches, seq, seq.len()
+                )));
+            }
+        }
+        let contaminants: Vec<Vec<u8>> = seqs.iter().map(|c| c.to_uppercase().into_bytes()).collect();
+        let matcher = if mismatches == 0 {
+            Some(AhoCorasickBuilder::new().ascii_case_insensitive(true).build(&contaminants))
+        } else {
+            None
+        };
+        Ok(Contaminants { contaminants, matcher, mismatches, removed: Cell::new(0) })
+    }
+
+    /// Exact matching goes through the Aho-Corasick automaton. With a mismatch budget, each motif
+    /// is slid along the read and compared by Hamming distance instead. Either way, soft-masked
+    /// lowercase bases match too.
+    fn contains_contaminant(&self, seq: &str) -> bool {
+        let seq = seq.trim_end().as_bytes();
+        match &self.matcher {
+            Some(matcher) => matcher.is_match(seq),
+            None => self.contaminants.iter().any(
+                |c| seq.windows(c.len()).any(
+                    |w| w.iter().zip(c).filter(|(a, b)| !a.eq_ignore_ascii_case(b)).count() <= self.mismatches
+                )
+            )
+        }
+    }
+}
+
+
+impl Criterion for Contaminants {
+    fn name(&self) -> &'static str { "contaminant" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        if self.contains_contaminant(&r1.seq) || self.contains_contaminant(&r2.seq) {
+            increment(&self.removed);
+            CheckResult::Fail
+        } else {
+            CheckResult::Pass
+        }
+    }
+
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        vec![("contaminant_pairs_removed", self.removed.get())]
+    }
+}
+
+
+pub struct Ambiguous {
+    max_frac: f64
+}
+
+
+impl Ambiguous {
+    pub fn new(max_frac: f64) -> Ambiguous {
+        Ambiguous { max_frac }
+    }
+}
+
+
+impl Criterion for Ambiguous {
+    fn name(&self) -> &'static str { "ambiguous" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        let is_ambiguous = |b: u8| !b"ACGTNacgtn".contains(&b);
+        (r1.base_fraction(is_ambiguous) <= self.max_frac && r2.base_fraction(is_ambiguous) <= self.max_frac).into()
+    }
+}
+
+
+/// Fraction of N bases in each read, over the part of it that hard trimming will keep
+pub struct NContent {
+    max_frac: f64,
+    trims: (i32, i32)
+}
+
+
+impl NContent {
+    pub fn new(max_frac: f64, trim_r1: Option<i32>, trim_r2: Option<i32>) -> NContent {
+        NContent { max_frac, trims: (trim_r1.unwrap_or(0), trim_r2.unwrap_or(0)) }
+    }
+
+    fn passes(&self, entry: &FastqEntry, trim: i32) -> bool {
+        let seq = hard_trimmed(&entry.seq, trim).as_bytes();
+        if seq.is_empty() {
+            return true;
+        }
+        let n_bases = seq.iter().filter(|b| b.eq_ignore_ascii_case(&b'N')).count();
+        n_bases as f64 / seq.len() as f64 <= self.max_frac
+    }
+}
+
+
+impl Criterion for NContent {
+    fn name(&self) -> &'static str { "n_content" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        (self.passes(r1, self.trims.0) && self.passes(r2, self.trims.1)).into()
+    }
+}
+
+
+pub struct QualAtPositions {
+    positions: Vec<(usize, u8)>
+}
+
+
+impl QualAtPositions {
+    pub fn new(positions: &[(usize, u8)]) -> QualAtPositions {
+        QualAtPositions { positions: positions.to_vec() }
+    }
+
+    fn passes(&self, entry: &FastqEntry) -> bool {
+        let qual = entry.qual.trim_end().as_bytes();
+        self.positions.iter().all(
+            |(pos, min_qual)| qual.get(*pos).is_none_or(|q| q.saturating_sub(entry.qual_offset) >= *min_qual)
+        )
+    }
+}
+
+
+impl Criterion for QualAtPositions {
+    fn name(&self) -> &'static str { "qual_at_positions" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        (self.passes(r1) && self.passes(r2)).into()
+    }
+}
+
+
+pub struct MeanQual {
+    min_qual: f64
+}
+
+
+impl MeanQual {
+    pub fn new(min_qual: f64) -> MeanQual {
+        MeanQual { min_qual }
+    }
+}
+
+
+impl Criterion for MeanQual {
+    fn name(&self) -> &'static str { "mean_qual" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        (r1.mean_qual() >= self.min_qual && r2.mean_qual() >= self.min_qual).into()
+    }
+}
+
+
+pub struct Q30 {
+    min_frac: f64
+}
+
+
+impl Q30 {
+    pub fn new(min_frac: f64) -> Q30 {
+        Q30 { min_frac }
+    }
+}
+
+
+impl Criterion for Q30 {
+    fn name(&self) -> &'static str { "q30" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        (r1.q30_fraction() >= self.min_frac && r2.q30_fraction() >= self.min_frac).into()
+    }
+}
+
+
+/// Mean quality over the first bases of each read
+pub struct AnchorQual {
+    min_qual: f64,
+    anchor_len: usize
+}
+
+
+impl AnchorQual {
+    pub fn new(min_qual: f64, anchor_len: usize) -> AnchorQual {
+        AnchorQual { min_qual, anchor_len }
+    }
+
+    fn passes(&self, entry: &FastqEntry) -> bool {
+        let anchor: Vec<u8> = entry.qual_scores().take(self.anchor_len).collect();
+        if anchor.is_empty() {
+            return true;
+        }
+        let mean_qual = anchor.iter().map(|q| f64::from(*q)).sum::<f64>() / anchor.len() as f64;
+        mean_qual >= self.min_qual
+    }
+}
+
+
+impl Criterion for AnchorQual {
+    fn name(&self) -> &'static str { "anchor_qual" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        (self.passes(r1) && self.passes(r2)).into()
+    }
+}
+
+
+pub struct DistinctBases {
+    min_bases: usize
+}
+
+
+impl DistinctBases {
+    pub fn new(min_bases: usize) -> DistinctBases {
+        DistinctBases { min_bases }
+    }
+}
+
+
+impl Criterion for DistinctBases {
+    fn name(&self) -> &'static str { "distinct_bases" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        (r1.distinct_bases() >= self.min_bases && r2.distinct_bases() >= self.min_bases).into()
+    }
+}
+
+
+pub struct InternalNRun {
+    max_run: usize
+}
+
+
+impl InternalNRun {
+    pub fn new(max_run: usize) -> InternalNRun {
+        InternalNRun { max_run }
+    }
+}
+
+
+impl Criterion for InternalNRun {
+    fn name(&self) -> &'static str { "internal_n" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        (r1.longest_internal_n_run() <= self.max_run && r2.longest_internal_n_run() <= self.max_run).into()
+    }
+}
+
+
+/// Pairs where R1 matches the reverse complement of R2 to within a number of mismatches
+pub struct RcIdentical {
+    max_mismatches: usize,
+    removed: Cell<i64>
+}
+
+
+impl RcIdentical {
+    pub fn new(max_mismatches: usize) -> RcIdentical {
+        RcIdentical { max_mismatches, removed: Cell::new(0) }
+    }
+
+    fn complement(base: u8) -> u8 {
+        match base.to_ascii_uppercase() {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N'
+        }
+    }
+}
+
+
+impl Criterion for RcIdentical {
+    fn name(&self) -> &'static str { "rc_identical" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        let seq_1 = r1.seq.trim_end().as_bytes();
+        let seq_2 = r2.seq.trim_end().as_bytes();
+        if seq_1.len() != seq_2.len() {
+            return CheckResult::Pass;
+        }
+
+        // Ns never match, so that pairs of mostly uncalled bases aren't mistaken for dimers
+        let mismatches = seq_1.iter().zip(seq_2.iter().rev()).filter(
+            |(b1, b2)| b1.to_ascii_uppercase() != RcIdentical::complement(**b2) || b1.eq_ignore_ascii_case(&b'N')
+        ).count();
+        if mismatches <= self.max_mismatches {
+            increment(&self.removed);
+            CheckResult::Fail
+        } else {
+            CheckResult::Pass
+        }
+    }
+
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        vec![("rc_identical_pairs_removed", self.removed.get())]
+    }
+}
+
+
+#[derive(Default)]
+pub struct MateLength {
+    removed: Cell<i64>
+}
+
+
+impl Criterion for MateLength {
+    fn name(&self) -> &'static str { "mate_length" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        if r1.seq_len() != r2.seq_len() {
+            increment(&self.removed);
+            CheckResult::Fail
+        } else {
+            CheckResult::Pass
+        }
+    }
+
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        vec![("unequal_length_pairs_removed", self.removed.get())]
+    }
+}
+
+
+#[derive(Default)]
+pub struct MateNumber {
+    removed: Cell<i64>
+}
+
+
+impl Criterion for MateNumber {
+    fn name(&self) -> &'static str { "mate_number" }
+
+    fn evaluate(&self, r1: &FastqEntry, r2: &FastqEntry) -> CheckResult {
+        if mate_number(&r1.id) == Some(1) && mate_number(&r2.id) == Some(2) {
+            CheckResult::Pass
+        } else {
+            increment(&self.removed);
+            CheckResult::Fail
+        }
+    }
+
+    fn counts(&self) -> Vec<(&'static str, i64)> {
+        vec![("mate_number_pairs_removed", self.removed.get())]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contaminants(seqs: &[&str], mismatches: usize) -> Result<Contaminants> {
+        Contaminants::new(&seqs.iter().map(|s| s.to_string()).collect::<Vec<_>>(), mismatches)
+    }
+
+    #[test]
+    fn empty_contaminant_is_refused() {
+        for mismatches in [0, 1] {
+            assert!(matches!(contaminants(&["ACGT", ""], mismatches), Err(FiltererError::Config(_))));
+        }
+    }
+
+    #[test]
+    fn contaminants_match_soft_masked_bases() {
+        for mismatches in [0, 1] {
+            let matcher = contaminants(&["ACGTACGT"], mismatches).unwrap();
+            assert!(matcher.contains_contaminant("TTacgtACGTTT\n"));
+            assert!(!matcher.contains_contaminant("TTTTTTTTTTTT\n"));
+        }
+    }
+
+    #[test]
+    fn mismatches_covering_a_whole_contaminant_are_refused() {
+        assert!(matches!(contaminants(&["ACGT", "AC"], 2), Err(FiltererError::Config(_))));
+        assert!(matches!(contaminants(&["ACGT"], 5), Err(FiltererError::Config(_))));
+        let matcher = contaminants(&["ACGT"], 3).unwrap();
+        assert!(matcher.contains_contaminant("TTTTAGGA\n"));
+        assert!(!matcher.contains_contaminant("TGCA\n"));
+    }
+}
@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+
+pub type Result<T> = std::result::Result<T, FiltererError>;
+
+
+#[derive(Debug)]
+pub enum FiltererError {
+    Io(io::Error),
+    CorruptGzip {
+        file: PathBuf,
+        record: u64,
+        source: io::Error
+    },
+    MalformedRecord {
+        file: PathBuf,
+        record: u64,
+        reason: String
+    },
+    MismatchedPair {
+        record: u64,
+        r1_id: String,
+        r2_id: String
+    },
+    Strict(String),
+    Config(String),
+    SelfTest(String)
+}
+
+
+impl fmt::Display for FiltererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FiltererError::Io(e) => write!(f, "{}", e),
+            FiltererError::CorruptGzip { file, record, source } => {
+                write!(f, "Corrupt gzip stream in {:?} near record {}: {}", file, record, source)
+            },
+            FiltererError::MalformedRecord { file, record, reason } => {
+                write!(f, "Malformed fastq record {} in {:?}: {}", record, file, reason)
+            },
+            FiltererError::MismatchedPair { record, r1_id, r2_id } => {
+                write!(f, "Read IDs of pair {} don't match: {} in R1 and {} in R2 - are the inputs out of sync?", record, r1_id, r2_id)
+            },
+            FiltererError::Strict(warning) => write!(f, "{} (warnings are errors under --strict)", warning),
+            FiltererError::Config(reason) => write!(f, "Invalid configuration: {}", reason),
+            FiltererError::SelfTest(reason) => write!(f, "Self-test failed: {}", reason)
+        }
+    }
+}
+
+
+impl Error for FiltererError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FiltererError::Io(e) => Some(e),
+            FiltererError::CorruptGzip { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+
+impl FiltererError {
+    /// Process exit code, distinguishing corrupt input transfers from other failures
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FiltererError::CorruptGzip { .. } => 3,
+            _ => 1
+        }
+    }
+}
+
+
+impl From<io::Error> for FiltererError {
+    fn from(e: io::Error) -> FiltererError {
+        if let Some(missing) = e.get_ref().and_then(|inner| inner.downcast_ref::<MissingCodecError>()) {
+            return FiltererError::Config(missing.to_string());
+        }
+        FiltererError::Io(e)
+    }
+}
+
+
+/// Wraps errors raised by the gzip decoder so that they can be told apart from other read errors
+/// after passing through a BufReader.
+#[derive(Debug)]
+pub struct GzipStreamError(pub io::Error);
+
+
+impl fmt::Display for GzipStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+
+impl Error for GzipStreamError {}
+
+
+/// Raised for a file whose extension needs a codec this build was compiled without, and turned
+/// into a Config error rather than reading or writing the file as plain text
+#[derive(Debug)]
+pub struct MissingCodecError {
+    pub file: PathBuf,
+    pub feature: &'static str
+}
+
+
+impl fmt::Display for MissingCodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} needs the '{}' codec, which was not compiled into this build", self.file, self.feature)
+    }
+}
+
+
+impl Error for MissingCodecError {}
@@ -0,0 +1,3959 @@
+extern crate aho_corasick;
+extern crate env_logger;
+#[cfg(feature="gzip")]
+extern crate flate2;
+extern crate log;
+extern crate quantiles;
+extern crate rand;
+extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate structopt;
+
+mod codec;
+mod criteria;
+pub mod error;
+mod remote;
+mod selftest;
+
+use std::cell::{Cell,RefCell};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap,BinaryHeap,HashMap,HashSet,VecDeque};
+use std::fmt;
+use std::fs::{File,OpenOptions};
+use std::io::{self,BufRead,BufReader,Write,BufWriter};
+use std::path::{Path,PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::thread;
+use env_logger::fmt::{Color,WriteStyle};
+use log::{info,debug,warn,log_enabled,Level};
+use quantiles::ckms::CKMS;
+use rand::{Rng,SeedableRng};
+use rand::rngs::StdRng;
+use regex::Regex;
+use serde::{Serialize,Serializer};
+use structopt::StructOpt;
+pub use codec::{Decoder,Encoder,Output};
+pub use codec::register as register_codec;
+use criteria::{CheckResult,Criterion};
+pub use criteria::PairFilter;
+use error::GzipStreamError;
+pub use error::{FiltererError,Result};
+
+
+#[derive(StructOpt,Serialize)]
+pub struct Cli {
+    #[structopt(long="i1", raw(required_unless_one=r#"&["parse_test", "selftest"]"#))]
+    i1: Option<PathBuf>,
+
+    /// R2 input. Without it, and without --concatenated_mates, --i1 is filtered as single-end reads
+    #[structopt(long="i2")]
+    i2: Option<PathBuf>,
+
+    /// Read both mates from --i1, which holds all R1 records followed by all R2 records, e.g. as
+    /// two gzip members. The switch to R2 is found from the mate number in the headers
+    #[structopt(long="concatenated_mates", raw(conflicts_with=r#""i2""#))]
+    concatenated_mates: bool,
+
+    /// Decompress an input of "-", i.e. stdin, as gzip
+    #[structopt(long="input_gzip")]
+    input_gzip: bool,
+
+    /// Compress outputs of "-", i.e. stdout, as gzip
+    #[structopt(long="output_gzip")]
+    output_gzip: bool,
+
+    /// Read both mates from --i1, where R1 and R2 records alternate. Outputs are split into R1 and R2
+    /// files as for separate inputs
+    #[structopt(long="interleaved", raw(conflicts_with_all=r#"&["i2", "concatenated_mates", "multiline"]"#))]
+    interleaved: bool,
+
+    #[structopt(long="f1")]
+    f1: Option<PathBuf>,
+
+    #[structopt(long="f2")]
+    f2: Option<PathBuf>,
+
+    #[structopt(long="o1")]
+    o1: Option<PathBuf>,
+
+    #[structopt(long="o2")]
+    o2: Option<PathBuf>,
+
+    #[structopt(long="s1")]
+    s1: Option<PathBuf>,
+
+    #[structopt(long="s2")]
+    s2: Option<PathBuf>,
+
+    /// Name outputs not given explicitly from a pattern, e.g. '{dir}/{base}.{kind}.fastq{gz}', where
+    /// {dir} and {base} come from the input, {mate} is R1 or R2, {kind} is kept, rejected or
+    /// singleton, and {gz} is '.gz' if outputs are compressed by default, i.e. with the gzip feature
+    #[structopt(long="output_template", parse(try_from_str="parse_output_template"))]
+    output_template: Option<String>,
+
+    /// Gzip compression level of outputs with a .gz extension, including the default ones, from 0
+    /// for fastest to 9 for smallest
+    #[structopt(long="compression_level", default_value="6", parse(try_from_str="parse_compression_level"))]
+    compression_level: u32,
+
+    /// Gzip compression level of kept outputs, including singletons, merged pairs and tile files,
+    /// if different from --compression_level
+    #[structopt(long="kept_compression", parse(try_from_str="parse_compression_level"))]
+    kept_compression: Option<u32>,
+
+    /// Gzip compression level of rejected outputs, if different from --compression_level
+    #[structopt(long="rejected_compression", parse(try_from_str="parse_compression_level"))]
+    rejected_compression: Option<u32>,
+
+    /// Threads to use, including the main one. Each extra thread, up to one per input, decompresses
+    /// an input in the background while the main one filters. Defaults to the number of CPUs, up to
+    /// 4
+    #[structopt(long="threads")]
+    threads: Option<usize>,
+
+    #[structopt(long="threshold", default_value="36")]
+    len_threshold: usize,
+
+    /// Minimum length of R1 only, overriding --threshold
+    #[structopt(long="threshold_r1")]
+    threshold_r1: Option<usize>,
+
+    /// Minimum length of R2 only, overriding --threshold
+    #[structopt(long="threshold_r2")]
+    threshold_r2: Option<usize>,
+
+    /// Remove read pairs where either mate is longer than this, e.g. concatemers
+    #[structopt(long="max_length")]
+    max_length: Option<usize>,
+
+    #[structopt(long="stats_file", parse(from_os_str))]
+    stats_file: Option<PathBuf>,
+
+    /// Format of --stats_file: 'text', 'json' for the run's paths, options and counters as a JSON
+    /// object, or 'fastp_json' for the subset of fastp's JSON report read by MultiQC
+    #[structopt(long="stats_format", default_value="text", raw(possible_values=r#"&["text", "json", "fastp_json"]"#))]
+    stats_format: StatsFormat,
+
+    /// Run only these criteria, in this order, rather than every criterion whose options are set.
+    /// Criteria that need options, e.g. 'tile' needs --remove_tiles, must still have them
+    #[structopt(
+        long="filters", raw(use_delimiter="true"),
+        raw(possible_values=r#"&[
+            "length", "unparseable_tile", "tile", "keep_tile", "id", "extract", "id_prefix", "region",
+            "contaminant", "ambiguous", "n_content", "qual_at_positions", "mean_qual", "anchor_qual", "q30",
+            "distinct_bases", "internal_n", "rc_identical", "mate_length", "mate_number"
+        ]"#)
+    )]
+    filters: Vec<String>,
+
+    /// Keep every read pair unchanged, applying no criteria or output changes, so that the run
+    /// only gathers stats
+    #[structopt(long="passthrough")]
+    passthrough: bool,
+
+    /// Write only this random fraction of removed read pairs to the filtered files. All removed
+    /// pairs are still counted
+    #[structopt(long="reject_sample_frac", parse(try_from_str="parse_fraction"))]
+    reject_sample_frac: Option<f64>,
+
+    /// Write kept read pairs ordered by 'tile', or by tile then cluster coordinates with 'coord'.
+    /// Output is only written once the whole input has been read
+    #[structopt(
+        long="sort_by", raw(possible_values=r#"&["tile", "coord"]"#),
+        raw(conflicts_with_all=r#"&["split_by_tile", "chunk_size", "checkpoint_file"]"#)
+    )]
+    sort_by: Option<SortBy>,
+
+    /// Megabytes of read pairs to hold in memory for --sort_by before spilling sorted runs to
+    /// --tmp_dir
+    #[structopt(long="sort_mem", default_value="1024")]
+    sort_mem: usize,
+
+    /// Directory for temporary files written while filtering, instead of $TMPDIR
+    #[structopt(long="tmp_dir", parse(from_os_str))]
+    tmp_dir: Option<PathBuf>,
+
+    /// Seed for random sampling, for reproducible runs. Sampling happens on the main thread in input
+    /// order, so a seeded run's output is the same whatever --threads is
+    #[structopt(long="seed")]
+    seed: Option<u64>,
+
+    /// Rewrite --stats_file with the counters so far every this many read pairs, so that long runs
+    /// can be monitored. Its status line reads 'running' until the final write
+    #[structopt(long="stats_flush_interval", parse(try_from_str="parse_positive"))]
+    stats_flush_interval: Option<u64>,
+
+    /// Leave breakdowns of read pairs that are zero, e.g. r2_too_short 0, out of the text stats
+    /// file. Options and totals are always written
+    #[structopt(long="stats_only_changed")]
+    stats_only_changed: bool,
+
+    #[structopt(long="remove_tiles")]
+    remove_tiles: Vec<String>,
+
+    /// File of tiles to remove, one per line, added to any given with --remove_tiles. Blank lines
+    /// and lines starting with '#' are ignored
+    #[structopt(long="remove_tiles_file", parse(from_os_str))]
+    remove_tiles_file: Option<PathBuf>,
+
+    /// Keep only read pairs from these tiles, removing those from any other tile or with no tile ID
+    #[structopt(long="keep_tiles", raw(conflicts_with_all=r#"&["remove_tiles", "remove_tiles_file"]"#))]
+    keep_tiles: Vec<String>,
+
+    /// Reject read pairs whose headers have no parseable tile ID. Otherwise they're kept without
+    /// being checked against --remove_tiles, and go to the 'unknown' tile with --split_by_tile
+    #[structopt(long="drop_unparseable_tiles")]
+    drop_unparseable_tiles: bool,
+
+    #[structopt(long="remove_reads", parse(from_os_str))]
+    remove_reads: Option<PathBuf>,
+
+    /// Keep only the read pairs listed in this file, sending everything else to the filtered files
+    #[structopt(long="extract_reads", parse(from_os_str))]
+    extract_reads: Option<PathBuf>,
+
+    /// Remove read pairs whose read ID starts with this, e.g. for systematically named spike-ins.
+    /// Can be given multiple times
+    #[structopt(long="remove_id_prefix")]
+    remove_id_prefixes: Vec<String>,
+
+    /// Remove read pairs where either mate contains this sequence. Can be given multiple times
+    #[structopt(long="contaminant_seq")]
+    contaminant_seqs: Vec<String>,
+
+    /// Number of mismatches allowed when matching --contaminant_seq, which must be fewer than the
+    /// length of the shortest one
+    #[structopt(long="contaminant_mismatches", default_value="0")]
+    contaminant_mismatches: usize,
+
+    /// Remove read pairs where either mate has more than this fraction of IUPAC ambiguity codes,
+    /// i.e. any base other than A, C, G, T or N
+    #[structopt(long="max_ambiguous_frac", parse(try_from_str="parse_fraction"))]
+    max_ambiguous_frac: Option<f64>,
+
+    /// Remove read pairs where either mate contains any IUPAC ambiguity code
+    #[structopt(long="remove_ambiguous")]
+    remove_ambiguous: bool,
+
+    /// Remove read pairs where either mate has more than this fraction of N bases, counted over
+    /// what --trim_r1 and --trim_r2 would leave of it
+    #[structopt(long="max_n_fraction", parse(try_from_str="parse_fraction"))]
+    max_n_fraction: Option<f64>,
+
+    /// Remove read pairs where either mate's quality at a 0-based position is below a cutoff,
+    /// given as pos:qual pairs, e.g. 0:20,74:30. Reads shorter than a position are not checked there
+    #[structopt(long="min_qual_at_positions", parse(try_from_str="parse_qual_position"), raw(use_delimiter="true"))]
+    min_qual_at_positions: Vec<(usize, u8)>,
+
+    /// Remove read pairs where either mate's sequence contains fewer than this many of the distinct
+    /// bases A, C, G, T and N, e.g. 2 removes poly-A reads
+    #[structopt(long="min_distinct_bases", parse(try_from_str="parse_distinct_bases"))]
+    min_distinct_bases: Option<usize>,
+
+    /// Remove read pairs where either mate has a run of more than this many Ns that doesn't touch
+    /// either end of the read
+    #[structopt(long="max_internal_n_run")]
+    max_internal_n_run: Option<usize>,
+
+    /// Remove read pairs where the two mates have different sequence lengths
+    #[structopt(long="require_equal_mate_length")]
+    require_equal_mate_length: bool,
+
+    /// Remove read pairs where R1 is the reverse complement of R2, e.g. palindromic adapter dimers
+    #[structopt(long="remove_rc_identical")]
+    remove_rc_identical: bool,
+
+    /// Number of mismatches allowed when comparing mates for --remove_rc_identical
+    #[structopt(long="rc_mismatches", default_value="1")]
+    rc_mismatches: usize,
+
+    /// Remove read pairs unless R1's header carries mate number 1 and R2's carries 2, either as a
+    /// /1 or /2 suffix or in the Casava comment
+    #[structopt(long="require_mate_number")]
+    require_mate_number: bool,
+
+    /// Remove read pairs where either mate's mean Phred quality is below this
+    #[structopt(long="min_mean_qual")]
+    min_mean_qual: Option<f64>,
+
+    /// Remove read pairs where either mate's mean quality over its first --anchor_len bases is below
+    /// this, as aligners seed from the start of the read
+    #[structopt(long="min_anchor_qual")]
+    min_anchor_qual: Option<f64>,
+
+    /// Number of bases at the 5' end checked by --min_anchor_qual
+    #[structopt(long="anchor_len", default_value="15")]
+    anchor_len: usize,
+
+    /// Remove read pairs where either mate has less than this fraction of bases at Q30 or above
+    #[structopt(long="min_q30_frac", parse(try_from_str="parse_fraction"))]
+    min_q30_frac: Option<f64>,
+
+    /// Write one tab-separated line per read pair recording whether it passed and which
+    /// criteria it failed. For single-end input, length_r2 is NA
+    #[structopt(long="annotation_file", parse(from_os_str))]
+    annotation_file: Option<PathBuf>,
+
+    /// Write a tab-separated count of read pairs per flowcell lane
+    #[structopt(long="lane_counts_file", parse(from_os_str))]
+    lane_counts_file: Option<PathBuf>,
+
+    /// Write a tab-separated table of the fraction of A, C, G, T and N at each position of R1 and
+    /// R2, as in FastQC's per base sequence content
+    #[structopt(long="base_content_file", parse(from_os_str))]
+    base_content_file: Option<PathBuf>,
+
+    /// If the first records suggest --i1 and --i2 were given the wrong way round, swap them rather
+    /// than just warning
+    #[structopt(long="fix_swapped")]
+    fix_swapped: bool,
+
+    /// Character separating the fields of the read ID, from which the lane, tile and cluster
+    /// coordinates are taken. The comment is still separated from the read ID by a space
+    #[structopt(long="field_delim", default_value=":")]
+    field_delim: char,
+
+    /// Extract the tile ID from the read header with a regex containing a named group
+    /// '(?P<tile>...)', instead of taking the fifth field of the read ID. The lane and cluster
+    /// coordinates are taken from optional groups '(?P<lane>...)', '(?P<x>...)' and '(?P<y>...)'
+    #[structopt(long="tile_regex", parse(try_from_str="parse_tile_regex"))]
+    #[serde(serialize_with="serialize_regex")]
+    tile_regex: Option<Regex>,
+
+    /// Replace each base of the kept reads whose quality is below this with N, keeping the read
+    /// length and qualities as they are
+    #[structopt(long="mask_below_qual")]
+    mask_below_qual: Option<u8>,
+
+    /// Trim trailing N bases, and their qualities, from each read before it's checked
+    #[structopt(long="trim_trailing_n")]
+    trim_trailing_n: bool,
+
+    /// ASCII offset of the input quality encoding, e.g. 64 for old Illumina Phred+64 data
+    #[structopt(long="qual_offset", default_value="33")]
+    qual_offset: u8,
+
+    /// Quality encoding offset of R1 only, overriding --qual_offset
+    #[structopt(long="qual_offset_r1")]
+    qual_offset_r1: Option<u8>,
+
+    /// Quality encoding offset of R2 only, overriding --qual_offset
+    #[structopt(long="qual_offset_r2")]
+    qual_offset_r2: Option<u8>,
+
+    /// Add a constant to every quality value of the kept reads before they're written, clamped to
+    /// Phred 0 to --max_qual. Filtering still uses the original qualities
+    #[structopt(long="qual_shift", raw(allow_hyphen_values="true"))]
+    qual_shift: Option<i32>,
+
+    /// Highest Phred score that --qual_shift and --quality_bins may produce. Raise this, up to 93,
+    /// for long-read data with a wider quality range
+    #[structopt(long="max_qual", default_value="40", parse(try_from_str="parse_max_qual"))]
+    max_qual: u8,
+
+    /// Reduce the quality values of kept reads to the bins given by --quality_bins, for smaller
+    /// compressed outputs. Applied after --qual_shift
+    #[structopt(long="bin_quality")]
+    bin_quality: bool,
+
+    /// Quality bins for --bin_quality, as min_qual:value pairs. Each quality is replaced by the
+    /// value of the bin with the highest min_qual not above it. Defaults to NovaSeq-style binning
+    #[structopt(
+        long="quality_bins", default_value="0:2,3:12,15:23,31:37",
+        parse(try_from_str="parse_quality_bin"), raw(use_delimiter="true")
+    )]
+    quality_bins: Vec<(u8, u8)>,
+
+    /// Write kept read pairs to per-tile files tile_<id>_R1.fastq.gz and tile_<id>_R2.fastq.gz in
+    /// this directory instead of to the output files
+    #[structopt(long="split_by_tile", parse(from_os_str))]
+    split_by_tile: Option<PathBuf>,
+
+    /// Maximum number of tiles to keep files open for with --split_by_tile. Others are closed and
+    /// reopened for appending as needed
+    #[structopt(long="max_open_tiles", default_value="64")]
+    max_open_tiles: usize,
+
+    /// Split the output files into numbered parts, e.g. sample_R1_filtered_part001.fastq.gz, of this
+    /// many kept read pairs each
+    #[structopt(
+        long="chunk_size", parse(try_from_str="parse_positive"),
+        raw(conflicts_with_all=r#"&["append", "resume"]"#)
+    )]
+    chunk_size: Option<u64>,
+
+    /// Skip read pairs where either record is malformed, e.g. with differing sequence and quality
+    /// lengths, logging a warning rather than stopping with an error
+    #[structopt(long="skip_malformed")]
+    skip_malformed: bool,
+
+    /// Carry on when R1 and R2 read IDs don't match, warning about the first such pair and counting
+    /// them in the stats, rather than stopping with an error
+    #[structopt(long="allow_unpaired")]
+    allow_unpaired: bool,
+
+    /// Write kept reads with just the read ID in the header, dropping the comment after the space
+    #[structopt(long="strip_header_comment")]
+    strip_header_comment: bool,
+
+    /// With --strip_header_comment, keep the mate number from the comment as a /1 or /2 suffix
+    #[structopt(long="keep_mate_number")]
+    keep_mate_number: bool,
+
+    /// Write read pairs failing any criterion to the output files as well, with ' FAIL:' and the
+    /// failed criteria appended to their headers. They're still counted as removed in the stats
+    #[structopt(long="tag_instead_of_filter")]
+    tag_instead_of_filter: bool,
+
+    /// Merge kept read pairs whose mates overlap into single reads written to --merged_out, e.g.
+    /// for amplicons with short inserts. Pairs that don't overlap are written as usual
+    #[structopt(long="merge", raw(requires=r#""merged_out""#))]
+    merge: bool,
+
+    #[structopt(long="merged_out", parse(from_os_str))]
+    merged_out: Option<PathBuf>,
+
+    /// Minimum number of overlapping bases between R1 and R2's reverse complement for --merge
+    #[structopt(long="min_overlap", default_value="10")]
+    min_overlap: usize,
+
+    /// Maximum fraction of mismatching bases in the overlap for --merge
+    #[structopt(long="max_overlap_mismatch_frac", default_value="0.1", parse(try_from_str="parse_fraction"))]
+    max_overlap_mismatch_frac: f64,
+
+    /// Remove read pairs whose cluster coordinates fall in any of the rectangular flowcell regions
+    /// in this file, one 'tile x_min y_min x_max y_max' per line
+    #[structopt(long="remove_regions", parse(from_os_str))]
+    remove_regions: Option<PathBuf>,
+
+    /// Treat every warning as an error, stopping the run
+    #[structopt(long="strict")]
+    strict: bool,
+
+    /// What to do with a read pair where one mate has no sequence, e.g. after trimming: 'reject'
+    /// the pair, or write the other mate to the singletons file given by --s1/--s2
+    #[structopt(long="on_empty_read", default_value="reject", raw(possible_values=r#"&["reject", "singleton"]"#))]
+    on_empty_read: OnEmptyRead,
+
+    /// If one input runs out before the other, write the rest of the longer one to its singletons
+    /// file given by --s1/--s2 rather than stopping with an error
+    #[structopt(long="pad_shorter_mate")]
+    pad_shorter_mate: bool,
+
+    /// Write the version, command line and effective parameters to <output>.params.json next to
+    /// each output file
+    #[structopt(long="embed_params_header")]
+    embed_params_header: bool,
+
+    /// Colour log output: 'auto' colours when stderr is a terminal and NO_COLOR is not set
+    #[structopt(long="color", default_value="auto", raw(possible_values=r#"&["auto", "always", "never"]"#))]
+    color: ColorChoice,
+
+    /// Periodically record how far through the input the run has got, so that it can be restarted
+    /// with --resume
+    #[structopt(long="checkpoint_file", parse(from_os_str))]
+    checkpoint_file: Option<PathBuf>,
+
+    /// Number of read pairs between checkpoints
+    #[structopt(long="checkpoint_interval", default_value="1000000", parse(try_from_str="parse_positive"))]
+    checkpoint_interval: u64,
+
+    /// Restart from --checkpoint_file, skipping the read pairs already processed and appending to
+    /// the outputs. Outputs are first cut back to their size at the checkpoint. Tile and annotation
+    /// files aren't checkpointed, so can't be resumed
+    #[structopt(
+        long="resume",
+        raw(requires=r#""checkpoint_file""#, conflicts_with_all=r#"&["split_by_tile", "annotation_file"]"#)
+    )]
+    resume: bool,
+
+    /// Accept records with the sequence and quality wrapped over several lines. Wrapped records are
+    /// written out on single lines unless --wrap is given
+    #[structopt(long="multiline")]
+    multiline: bool,
+
+    /// Wrap the sequence and quality lines of written records at this many characters, for tools
+    /// that expect wrapped fastq
+    #[structopt(long="wrap", parse(try_from_str="parse_wrap"), raw(conflicts_with=r#""sort_by""#))]
+    wrap: Option<usize>,
+
+    /// Parse a single read header as a run would, print the fields extracted from it and exit.
+    /// Useful for checking --tile_regex against a sample of the data
+    #[structopt(long="parse_test", raw(allow_hyphen_values="true"))]
+    parse_test: Option<String>,
+
+    /// Filter a generated dataset with known contents, check the kept and rejected counts and exit
+    #[structopt(long="selftest", raw(hidden="true"))]
+    selftest: bool,
+
+    /// Print the number of records in each input and exit without filtering
+    #[structopt(long="count_only")]
+    count_only: bool,
+
+    /// Append to existing output and filtered files instead of overwriting them. The stats file
+    /// still only describes the current invocation
+    #[structopt(long="append")]
+    append: bool,
+
+    /// Hard-trim this many bases from each kept R1, from the 5' end if positive or the 3' end if
+    /// negative. This happens after filtering, so a kept read can end up shorter than --threshold
+    #[structopt(long="trim_r1", raw(allow_hyphen_values="true"))]
+    trim_r1: Option<i32>,
+
+    /// As --trim_r1, for R2
+    #[structopt(long="trim_r2", raw(allow_hyphen_values="true"))]
+    trim_r2: Option<i32>
+}
+
+
+#[derive(PartialEq,Serialize)]
+#[serde(rename_all="lowercase")]
+enum OnEmptyRead {
+    Reject,
+    Singleton
+}
+
+
+impl FromStr for OnEmptyRead {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<OnEmptyRead, String> {
+        match s {
+            "reject" => Ok(OnEmptyRead::Reject),
+            "singleton" => Ok(OnEmptyRead::Singleton),
+            _ => Err(format!("Unknown action '{}'", s))
+        }
+    }
+}
+
+
+#[derive(PartialEq,Serialize)]
+#[serde(rename_all="snake_case")]
+enum StatsFormat {
+    Text,
+    Json,
+    FastpJson
+}
+
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<StatsFormat, String> {
+        match s {
+            "text" => Ok(StatsFormat::Text),
+            "json" => Ok(StatsFormat::Json),
+            "fastp_json" => Ok(StatsFormat::FastpJson),
+            _ => Err(format!("Unknown stats format '{}'", s))
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+#[serde(rename_all="lowercase")]
+enum SortBy {
+    Tile,
+    Coord
+}
+
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<SortBy, String> {
+        match s {
+            "tile" => Ok(SortBy::Tile),
+            "coord" => Ok(SortBy::Coord),
+            _ => Err(format!("Unknown sort order '{}'", s))
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+#[serde(rename_all="lowercase")]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never
+}
+
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ColorChoice, String> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Unknown colour choice '{}'", s))
+        }
+    }
+}
+
+
+const PHRED_OFFSET: u8 = 33;
+/// Highest Phred score representable in printable ASCII
+const MAX_PHRED: u8 = 93;
+/// Most threads used without --threads, so as not to take over a shared machine
+const MAX_DEFAULT_THREADS: usize = 4;
+
+
+impl Cli {
+    /// Input files, which are required unless running --parse_test. With --concatenated_mates or
+    /// --interleaved both mates come from --i1, and for single-end input --i1 is given for both
+    fn inputs(&self) -> (&Path, &Path) {
+        let i1 = self.i1.as_ref().expect("--i1 is required");
+        (i1, self.i2.as_deref().unwrap_or(i1))
+    }
+
+    /// Whether there's no R2, so each record of --i1 is checked on its own
+    fn single_end(&self) -> bool {
+        self.i2.is_none() && !self.concatenated_mates && !self.interleaved
+    }
+
+    /// Number of mates in each record: 1 for single-end input, else 2
+    fn mates(&self) -> u8 {
+        if self.single_end() { 1 } else { 2 }
+    }
+
+    /// Extensions whose codecs to read stdin and write stdout with, as they have none of their own
+    fn stdio_exts(&self) -> (Option<&'static str>, Option<&'static str>) {
+        let gzip = |given: bool| if given { Some("gz") } else { None };
+        (gzip(self.input_gzip), gzip(self.output_gzip))
+    }
+
+    /// Minimum lengths of R1 and R2
+    fn thresholds(&self) -> (usize, usize) {
+        (self.threshold_r1.unwrap_or(self.len_threshold), self.threshold_r2.unwrap_or(self.len_threshold))
+    }
+
+    /// Compression levels of kept and rejected outputs
+    fn compression_levels(&self) -> (u32, u32) {
+        (
+            self.kept_compression.unwrap_or(self.compression_level),
+            self.rejected_compression.unwrap_or(self.compression_level)
+        )
+    }
+
+    /// Threads to use: as given, else one per CPU up to MAX_DEFAULT_THREADS
+    fn threads(&self) -> usize {
+        self.threads.unwrap_or_else(
+            || thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_DEFAULT_THREADS)
+        )
+    }
+
+    /// Where any feature spilling to disk should put its temporary files
+    pub fn tmp_dir(&self) -> PathBuf {
+        self.tmp_dir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// Quality encoding offsets of R1 and R2
+    fn qual_offsets(&self) -> (u8, u8) {
+        (self.qual_offset_r1.unwrap_or(self.qual_offset), self.qual_offset_r2.unwrap_or(self.qual_offset))
+    }
+
+    /// Where to write one kind of output for mate 1 or 2: the path given for it, else one from
+    /// --output_template, else the name of the input read as that mate with the default suffix for
+    /// the kind. With `swapped`, as under --fix_swapped, mate 1 is read from --i2 and vice versa
+    fn output_path(&self, mate: u8, kind: OutputKind, swapped: bool) -> PathBuf {
+        let (i1, i2) = self.inputs();
+        let (i1, i2) = if swapped { (i2, i1) } else { (i1, i2) };
+        let (tag_1, tag_2) = self.mate_tags();
+        let (input_file, tag, outputs) = match mate {
+            1 => (i1, tag_1, [&self.o1, &self.f1, &self.s1]),
+            _ => (i2, tag_2, [&self.o2, &self.f2, &self.s2])
+        };
+        let explicit = match kind {
+            OutputKind::Kept => outputs[0],
+            OutputKind::Rejected => outputs[1],
+            OutputKind::Singleton => outputs[2]
+        };
+        if let Some(file_path) = explicit {
+            return file_path.to_path_buf();
+        }
+
+        let (dir, base) = FastqHandler::input_dir_and_base(input_file);
+        match &self.output_template {
+            Some(template) => PathBuf::from(
+                template.replace("{dir}", if dir.is_empty() { "." } else { &dir }).replace("{base}", &format!("{}{}", base, tag))
+                    .replace("{mate}", &format!("R{}", mate)).replace("{kind}", kind.name())
+                    .replace("{gz}", codec::OUTPUT_SUFFIX)
+            ),
+            None => Path::new(&dir).join(format!("{}{}{}{}", base, tag, kind.default_ext(), codec::OUTPUT_SUFFIX))
+        }
+    }
+
+    /// Whether any tiles are to be removed, from --remove_tiles or --remove_tiles_file
+    fn removes_tiles(&self) -> bool {
+        !self.remove_tiles.is_empty() || self.remove_tiles_file.is_some()
+    }
+
+    /// Whether any records can be written to the singletons files
+    fn writes_singletons(&self) -> bool {
+        self.on_empty_read == OnEmptyRead::Singleton || self.pad_shorter_mate
+    }
+
+    /// Distinguishes default output names when both mates come from the same input
+    fn mate_tags(&self) -> (&'static str, &'static str) {
+        if self.concatenated_mates || self.interleaved { ("_R1", "_R2") } else { ("", "") }
+    }
+}
+
+
+fn parse_qual_position(s: &str) -> std::result::Result<(usize, u8), String> {
+    let mut parts = s.splitn(2, ':');
+    let pos = parts.next().and_then(|p| p.trim().parse().ok());
+    let qual = parts.next().and_then(|q| q.trim().parse().ok());
+    match (pos, qual) {
+        (Some(pos), Some(qual)) => Ok((pos, qual)),
+        _ => Err(format!("Expected pos:qual, got '{}'", s))
+    }
+}
+
+
+fn parse_fraction(s: &str) -> std::result::Result<f64, String> {
+    match s.parse() {
+        Ok(frac) if (0.0..=1.0).contains(&frac) => Ok(frac),
+        _ => Err(format!("Expected a fraction from 0 to 1, got '{}'", s))
+    }
+}
+
+
+fn parse_distinct_bases(s: &str) -> std::result::Result<usize, String> {
+    match s.parse() {
+        Ok(n) if (1..=5).contains(&n) => Ok(n),
+        _ => Err(format!("Expected a number of bases from 1 to 5, got '{}'", s))
+    }
+}
+
+
+fn parse_max_qual(s: &str) -> std::result::Result<u8, String> {
+    match s.parse() {
+        Ok(q) if q <= MAX_PHRED => Ok(q),
+        _ => Err(format!("Expected a Phred score of at most {}, got '{}'", MAX_PHRED, s))
+    }
+}
+
+
+fn parse_compression_level(s: &str) -> std::result::Result<u32, String> {
+    match s.parse() {
+        Ok(level) if level <= 9 => Ok(level),
+        _ => Err(format!("Expected a compression level from 0 to 9, got '{}'", s))
+    }
+}
+
+
+fn parse_positive(s: &str) -> std::result::Result<u64, String> {
+    match s.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Expected a whole number greater than 0, got '{}'", s))
+    }
+}
+
+
+fn parse_wrap(s: &str) -> std::result::Result<usize, String> {
+    match s.parse() {
+        Ok(width) if width > 0 => Ok(width),
+        _ => Err(format!("Expected a line width of at least 1, got '{}'", s))
+    }
+}
+
+
+fn parse_quality_bin(s: &str) -> std::result::Result<(u8, u8), String> {
+    let mut parts = s.splitn(2, ':');
+    let min_qual = parts.next().and_then(|q| q.trim().parse().ok());
+    let value = parts.next().and_then(|v| v.trim().parse().ok());
+    match (min_qual, value) {
+        (Some(min_qual), Some(value)) if value <= MAX_PHRED => Ok((min_qual, value)),
+        _ => Err(format!("Expected min_qual:value with a value of at most {}, got '{}'", MAX_PHRED, s))
+    }
+}
+
+
+/// Map every quality character, in the encoding with this offset, to that of its bin
+fn quality_bin_table(bins: &[(u8, u8)], qual_offset: u8) -> [u8; 256] {
+    let mut bins = bins.to_vec();
+    bins.sort();
+    let mut table = [0; 256];
+    for (c, binned) in table.iter_mut().enumerate() {
+        let qual = (c as u8).saturating_sub(qual_offset);
+        *binned = match bins.iter().rev().find(|(min_qual, _)| *min_qual <= qual) {
+            Some((_, value)) => value.saturating_add(qual_offset),
+            None => c as u8
+        };
+    }
+    table
+}
+
+
+fn serialize_regex<S: Serializer>(regex: &Option<Regex>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match regex {
+        Some(regex) => serializer.serialize_some(regex.as_str()),
+        None => serializer.serialize_none()
+    }
+}
+
+
+fn parse_output_template(s: &str) -> std::result::Result<String, String> {
+    let mut remaining = s.to_string();
+    for placeholder in &["{dir}", "{base}", "{mate}", "{kind}", "{gz}"] {
+        remaining = remaining.replace(placeholder, "");
+    }
+    if remaining.contains('{') || remaining.contains('}') {
+        return Err(format!("Unknown placeholder in output template '{}'", s));
+    }
+    Ok(s.to_string())
+}
+
+
+fn parse_tile_regex(s: &str) -> std::result::Result<Regex, String> {
+    let regex = Regex::new(s).map_err(|e| e.to_string())?;
+    if !regex.capture_names().any(|name| name == Some("tile")) {
+        return Err("Tile regex must contain a named group '(?P<tile>...)'".to_string());
+    }
+    Ok(regex)
+}
+
+
+/// A read ID without any /1 or /2 mate suffix
+fn strip_mate_suffix(read_id: &str) -> &str {
+    read_id.strip_suffix("/1").or_else(|| read_id.strip_suffix("/2")).unwrap_or(read_id)
+}
+
+
+/// Whether two read IDs name mates, ignoring any /1 and /2 suffixes
+fn reads_are_paired(r1_id: &str, r2_id: &str) -> bool {
+    strip_mate_suffix(r1_id) == strip_mate_suffix(r2_id)
+}
+
+
+/// Find the mate number of a read from its header, either from a /1 or /2 suffix on the read ID
+/// or from the read number field of a Casava 1.8 comment, e.g. '1:N:0:ATCACG'.
+fn mate_number(header: &str) -> Option<u8> {
+    let header = header.trim_end();
+    let mut fields = header.splitn(2, |c: char| c.is_whitespace());
+    let read_id = fields.next().unwrap_or("");
+    if read_id.ends_with("/1") {
+        return Some(1);
+    } else if read_id.ends_with("/2") {
+        return Some(2);
+    }
+
+    let comment = fields.next()?.trim_start();
+    if comment.contains(':') {
+        match comment.split(':').next() {
+            Some("1") => return Some(1),
+            Some("2") => return Some(2),
+            _ => {}
+        }
+    }
+    None
+}
+
+
+/// An interleaved input, with records read but not yet taken by the MateReader for their mate
+struct InterleavedSource {
+    reader: Box<dyn BufRead>,
+    pending: [VecDeque<Vec<u8>>; 2],
+    next_mate: usize
+}
+
+
+impl InterleavedSource {
+    /// The next record for mate 0 or 1, reading past any for the other mate. Empty at the end of
+    /// the input
+    fn next_record(&mut self, mate: usize) -> io::Result<Vec<u8>> {
+        while self.pending[mate].is_empty() {
+            let mut record = Vec::new();
+            for _ in 0..4 {
+                if self.reader.read_until(b'\n', &mut record)? == 0 {
+                    break;
+                }
+            }
+            if record.is_empty() {
+                return Ok(record);
+            }
+            self.pending[self.next_mate].push_back(record);
+            self.next_mate = 1 - self.next_mate;
+        }
+        Ok(self.pending[mate].pop_front().unwrap_or_default())
+    }
+}
+
+
+/// Reads one mate's records from an interleaved input, as if they were in a file of their own
+struct MateReader {
+    source: Rc<RefCell<InterleavedSource>>,
+    mate: usize,
+    record: Vec<u8>,
+    pos: usize
+}
+
+
+impl io::Read for MateReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+
+impl BufRead for MateReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.record.len() {
+            self.record = self.source.borrow_mut().next_record(self.mate)?;
+            self.pos = 0;
+        }
+        Ok(&self.record[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.record.len());
+    }
+}
+
+
+/// Read the header, sequence, separator and quality lines of the next record. With `multiline`, the
+/// sequence runs up to the '+' line, and quality lines are joined until they cover the sequence, as
+/// a quality line can itself start with '@'.
+fn read_record(reader: &mut dyn BufRead, lines: [&mut String; 4], multiline: bool) -> io::Result<()> {
+    let [id, seq, strand, qual] = lines;
+    if !multiline {
+        for line in [id, seq, strand, qual] {
+            reader.read_line(line)?;
+        }
+        return Ok(());
+    }
+
+    if reader.read_line(id)? == 0 {
+        return Ok(());
+    }
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && !line.starts_with('+') {
+        seq.push_str(line.trim_end());
+        line.clear();
+    }
+    strand.push_str(&line);
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        qual.push_str(line.trim_end());
+        if qual.len() >= seq.len() {
+            break;
+        }
+    }
+    seq.push('\n');
+    qual.push('\n');
+    Ok(())
+}
+
+
+/// Write to a temporary file and rename it into place, so that readers never see a partial file
+fn write_atomically(file_path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_path = file_path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, file_path)
+}
+
+
+/// Tile IDs that look numeric are compared as integers, so "01101" and "1101" match. Anything
+/// else is compared as a plain string.
+fn normalise_tile(tile: &str) -> String {
+    let tile = tile.trim();
+    if !tile.is_empty() && tile.bytes().all(|b| b.is_ascii_digit()) {
+        if let Ok(n) = tile.parse::<u64>() {
+            return n.to_string();
+        }
+    }
+    tile.to_string()
+}
+
+
+/// One fastq record, with the fields parsed from its header
+pub struct FastqEntry {
+    id: String,
+    seq: String,
+    strand: String,
+    qual: String,
+    lane: String,
+    tile_id: String,
+    read_id: String,
+    x: Option<u32>,
+    y: Option<u32>,
+    qual_offset: u8
+}
+
+
+impl FastqEntry {
+    fn new() -> FastqEntry {
+        FastqEntry {
+            id: String::new(),
+            seq: String::new(),
+            strand: String::new(),
+            qual: String::new(),
+            lane: String::new(),
+            tile_id: String::new(),
+            read_id: String::new(),
+            x: None,
+            y: None,
+            qual_offset: PHRED_OFFSET
+        }
+    }
+
+    fn clear(&mut self) {
+        self.id.clear();
+        self.seq.clear();
+        self.strand.clear();
+        self.qual.clear();
+        self.lane.clear();
+        self.tile_id.clear();
+        self.read_id.clear();
+        self.x = None;
+        self.y = None;
+    }
+
+    /// The header line, including the leading '@'
+    pub fn header(&self) -> &str {
+        self.id.trim_end()
+    }
+
+    pub fn sequence(&self) -> &str {
+        self.seq.trim_end()
+    }
+
+    pub fn quality(&self) -> &str {
+        self.qual.trim_end()
+    }
+
+    /// The first word of the header, including the leading '@'
+    pub fn read_id(&self) -> &str {
+        &self.read_id
+    }
+
+    /// The tile ID, or an empty string if the header doesn't have one
+    pub fn tile_id(&self) -> &str {
+        &self.tile_id
+    }
+
+    pub fn lane(&self) -> &str {
+        &self.lane
+    }
+
+    /// The x and y cluster coordinates, if the header has them
+    pub fn coordinates(&self) -> Option<(u32, u32)> {
+        self.x.zip(self.y)
+    }
+
+    pub fn seq_len(&self) -> usize {
+        self.seq.trim_end().chars().count()
+    }
+
+    /// Phred scores decoded from the quality line
+    pub fn qual_scores<'b>(&'b self) -> impl Iterator<Item=u8> + 'b {
+        self.qual.trim_end().bytes().map(move |q| q.saturating_sub(self.qual_offset))
+    }
+
+    fn mean_qual(&self) -> f64 {
+        let n_bases = self.qual.trim_end().len();
+        if n_bases == 0 {
+            return 0.0;
+        }
+        self.qual_scores().map(f64::from).sum::<f64>() / n_bases as f64
+    }
+
+    fn q30_bases(&self) -> usize {
+        self.qual_scores().filter(|q| *q >= 30).count()
+    }
+
+    fn q30_fraction(&self) -> f64 {
+        let n_bases = self.qual.trim_end().len();
+        if n_bases == 0 { 0.0 } else { self.q30_bases() as f64 / n_bases as f64 }
+    }
+
+    /// Recalibrate quality values by a constant, keeping them within Phred 0 to max_qual
+    fn shift_qual(&mut self, shift: i32, max_qual: u8) {
+        let min = i32::from(self.qual_offset);
+        let max = i32::from(self.qual_offset.saturating_add(max_qual));
+        let shifted: String = self.qual.trim_end().bytes().map(
+            |q| (i32::from(q) + shift).max(min).min(max) as u8 as char
+        ).collect();
+        let line_ending = &self.qual[self.qual.trim_end().len()..];
+        self.qual = shifted + line_ending;
+    }
+
+    fn mask_below_qual(&mut self, min_qual: u8) {
+        let masked: String = self.seq.trim_end().bytes().zip(self.qual_scores()).map(
+            |(b, q)| if q < min_qual { 'N' } else { b as char }
+        ).collect();
+        let line_ending = &self.seq[self.seq.trim_end().len()..];
+        self.seq = masked + line_ending;
+    }
+
+    /// Remove any run of N bases from the 3' end, returning the number of bases removed
+    fn trim_trailing_n(&mut self) -> usize {
+        let seq = self.seq.trim_end();
+        let trimmed_len = seq.trim_end_matches(['N', 'n']).len();
+        let n_trimmed = seq.len() - trimmed_len;
+        if n_trimmed > 0 {
+            let line_ending = self.seq[seq.len()..].to_string();
+            self.seq.truncate(trimmed_len);
+            self.seq.push_str(&line_ending);
+            let line_ending = self.qual[self.qual.trim_end().len()..].to_string();
+            self.qual.truncate(trimmed_len.min(self.qual.trim_end().len()));
+            self.qual.push_str(&line_ending);
+        }
+        n_trimmed
+    }
+
+    /// Remove a fixed number of bases and their qualities, from the 5' end if `bases` is positive or
+    /// the 3' end if negative. Reads no longer than that are left empty
+    fn hard_trim(&mut self, bases: i32) {
+        let trim = |line: &str| format!("{}{}", hard_trimmed(line, bases), &line[line.trim_end().len()..]);
+        self.seq = trim(&self.seq);
+        self.qual = trim(&self.qual);
+    }
+
+    fn bin_qual(&mut self, table: &[u8; 256]) {
+        let binned: String = self.qual.trim_end().bytes().map(|q| table[q as usize] as char).collect();
+        let line_ending = &self.qual[self.qual.trim_end().len()..];
+        self.qual = binned + line_ending;
+    }
+
+    /// Add text to the end of the header line, before its line ending
+    fn append_to_header(&mut self, text: &str) {
+        let line_ending = self.id[self.id.trim_end().len()..].to_string();
+        self.id.truncate(self.id.trim_end().len());
+        self.id.push_str(text);
+        self.id.push_str(&line_ending);
+    }
+
+    /// Replace the header with just the read ID, optionally carrying over the mate number
+    fn strip_header_comment(&mut self, keep_mate_number: bool) {
+        let mut header = self.read_id.clone();
+        if keep_mate_number && !header.ends_with("/1") && !header.ends_with("/2") {
+            if let Some(mate) = mate_number(&self.id) {
+                header = format!("{}/{}", header, mate);
+            }
+        }
+        header.push('\n');
+        self.id = header;
+    }
+
+    /// What's wrong with the layout of a record that was read, if anything, e.g. a file ending part
+    /// way through it
+    fn structure_error(&self) -> Option<&'static str> {
+        if !self.id.starts_with('@') {
+            Some("header line does not start with '@'")
+        } else if self.seq.is_empty() || self.strand.is_empty() || self.qual.is_empty() {
+            Some("the file ends part way through the record")
+        } else if !self.strand.starts_with('+') {
+            Some("separator line does not start with '+'")
+        } else {
+            None
+        }
+    }
+
+    /// Populate the read ID, lane, tile and cluster coordinates from the header line
+    fn parse_header(&mut self, tile_regex: Option<&Regex>, field_delim: char) {
+        let header = self.id.trim_end();
+        let read_id = &header[0..header.find(' ').unwrap_or(header.len())];
+        self.tile_id = match tile_regex {
+            Some(regex) => {
+                let captures = regex.captures(self.id.trim_end());
+                let group = |name| captures.as_ref().and_then(|c| c.name(name)).map(|m| m.as_str());
+                self.lane = group("lane").unwrap_or("").to_string();
+                self.x = group("x").and_then(|x| x.parse().ok());
+                self.y = group("y").and_then(|y| y.parse().ok());
+                normalise_tile(group("tile").unwrap_or(""))
+            },
+            None => {
+                let parts = &mut read_id.split(field_delim);
+                self.lane = parts.nth(3).unwrap_or("").to_string();
+                let tile_id = normalise_tile(parts.next().unwrap_or(""));
+                self.x = parts.next().and_then(|x| x.parse().ok());
+                self.y = parts.next().and_then(|y| y.parse().ok());
+                tile_id
+            }
+        };
+        self.read_id = read_id.to_string();
+    }
+
+    /// Number of the bases A, C, G, T and N present in the sequence, ignoring case
+    fn distinct_bases(&self) -> usize {
+        let seq = self.seq.trim_end().as_bytes();
+        b"ACGTN".iter().filter(|b| seq.iter().any(|s| s.to_ascii_uppercase() == **b)).count()
+    }
+
+    /// Length of the longest run of Ns, ignoring case, with other bases on both sides of it
+    fn longest_internal_n_run(&self) -> usize {
+        let is_n = |c: char| c == 'N' || c == 'n';
+        self.seq.trim_end().trim_matches(is_n).split(|c: char| !is_n(c)).map(str::len).max().unwrap_or(0)
+    }
+
+    /// Fraction of bases in the sequence matching a predicate, or 0 for an empty sequence
+    fn base_fraction<F: Fn(u8) -> bool>(&self, f: F) -> f64 {
+        let seq = self.seq.trim_end().as_bytes();
+        if seq.is_empty() {
+            return 0.0;
+        }
+        seq.iter().filter(|b| f(**b)).count() as f64 / seq.len() as f64
+    }
+}
+
+
+/// What --trim_r1 or --trim_r2 would leave of a sequence or quality line, without its line ending
+fn hard_trimmed(line: &str, bases: i32) -> &str {
+    let content = line.trim_end();
+    let n = (bases.unsigned_abs() as usize).min(content.len());
+    if bases >= 0 { &content[n..] } else { &content[..content.len() - n] }
+}
+
+
+/// Merge R1 with R2's reverse complement where R1's 3' end overlaps it, trying the longest overlap
+/// first. Overlapping bases that agree get the sum of their qualities, capped at max_qual, and ones
+/// that disagree take the better base with the difference of the qualities. Returns the merged
+/// sequence and qualities, encoded with R1's quality offset
+fn merge_mates(r1: &FastqEntry, r2: &FastqEntry, min_overlap: usize, max_mismatch_frac: f64, max_qual: u8) -> Option<(String, String)> {
+    let complement = |b: u8| match b.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N'
+    };
+    let seq_1 = r1.sequence().as_bytes();
+    let qual_1: Vec<u8> = r1.qual_scores().collect();
+    let seq_2: Vec<u8> = r2.sequence().bytes().rev().map(complement).collect();
+    let qual_2: Vec<u8> = r2.qual_scores().collect::<Vec<u8>>().into_iter().rev().collect();
+
+    let overlap = (min_overlap.max(1)..=seq_1.len().min(seq_2.len())).rev().find(
+        |overlap| {
+            let start = seq_1.len() - overlap;
+            let mismatches = seq_1[start..].iter().zip(&seq_2[..*overlap]).filter(
+                |(b1, b2)| !b1.eq_ignore_ascii_case(b2)
+            ).count();
+            mismatches as f64 <= max_mismatch_frac * *overlap as f64
+        }
+    )?;
+
+    let start = seq_1.len() - overlap;
+    let mut seq: Vec<u8> = seq_1[..start].to_vec();
+    let mut qual: Vec<u8> = qual_1[..start].to_vec();
+    for i in 0..overlap {
+        let (b1, q1, b2, q2) = (seq_1[start + i], qual_1[start + i], seq_2[i], qual_2[i]);
+        if b1.eq_ignore_ascii_case(&b2) {
+            seq.push(b1);
+            qual.push(q1.saturating_add(q2).min(max_qual));
+        } else {
+            seq.push(if q1 >= q2 { b1 } else { b2 });
+            qual.push(q1.max(q2) - q1.min(q2));
+        }
+    }
+    seq.extend_from_slice(&seq_2[overlap..]);
+    qual.extend_from_slice(&qual_2[overlap..]);
+
+    let seq = seq.into_iter().map(char::from).collect();
+    let qual = qual.into_iter().map(|q| char::from(q.saturating_add(r1.qual_offset))).collect();
+    Some((seq, qual))
+}
+
+
+impl fmt::Display for FastqEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "read: {}seq: {}strand: {}qual: {}tile: {}",
+            self.id, self.seq, self.strand, self.qual, self.tile_id
+        )
+    }
+}
+
+
+/// Running summary of read lengths, cheap enough to update for every read
+#[derive(Default)]
+struct LengthStats {
+    min: usize,
+    max: usize,
+    total: u64,
+    count: u64
+}
+
+
+impl LengthStats {
+    fn add(&mut self, len: usize) {
+        if self.count == 0 || len < self.min {
+            self.min = len;
+        }
+        self.max = self.max.max(len);
+        self.total += len as u64;
+        self.count += 1;
+    }
+
+    fn mean(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { self.total as f64 / self.count as f64 }
+    }
+}
+
+
+/// Running totals of bases and their qualities, taken over all read pairs or just the kept ones
+#[derive(Default)]
+struct BaseCounts {
+    read_pairs: u64,
+    r1_bases: u64,
+    r2_bases: u64,
+    q20_bases: u64,
+    q30_bases: u64,
+    gc_bases: u64
+}
+
+
+impl BaseCounts {
+    fn add_pair(&mut self, r1: &FastqEntry, r2: &FastqEntry) {
+        self.read_pairs += 1;
+        self.r1_bases += r1.seq_len() as u64;
+        self.r2_bases += r2.seq_len() as u64;
+        for entry in &[r1, r2] {
+            self.q20_bases += entry.qual_scores().filter(|q| *q >= 20).count() as u64;
+            self.q30_bases += entry.q30_bases() as u64;
+            self.gc_bases += entry.seq.trim_end().bytes().filter(|b| b"GCgc".contains(b)).count() as u64;
+        }
+    }
+
+    fn total_bases(&self) -> u64 {
+        self.r1_bases + self.r2_bases
+    }
+
+    fn fraction(&self, n_bases: u64) -> f64 {
+        if self.total_bases() == 0 { 0.0 } else { n_bases as f64 / self.total_bases() as f64 }
+    }
+
+    /// Summary section of a fastp JSON report, leaving out R2's mean length for single-end input as
+    /// fastp does
+    fn fastp_summary(&self, mates: u8) -> serde_json::Value {
+        let mean_len = |n_bases: u64| n_bases.checked_div(self.read_pairs).unwrap_or(0);
+        let mut summary = serde_json::json!({
+            "total_reads": self.read_pairs * u64::from(mates),
+            "total_bases": self.total_bases(),
+            "q20_bases": self.q20_bases,
+            "q30_bases": self.q30_bases,
+            "q20_rate": self.fraction(self.q20_bases),
+            "q30_rate": self.fraction(self.q30_bases),
+            "read1_mean_length": mean_len(self.r1_bases),
+            "read2_mean_length": mean_len(self.r2_bases),
+            "gc_content": self.fraction(self.gc_bases)
+        });
+        if mates == 1 {
+            summary.as_object_mut().map(|s| s.remove("read2_mean_length"));
+        }
+        summary
+    }
+}
+
+
+/// Counts of A, C, G, T and N at each position of R1 and R2, with any other base counted as N
+#[derive(Default)]
+struct BaseContent {
+    r1: Vec<[u64; 5]>,
+    r2: Vec<[u64; 5]>
+}
+
+
+impl BaseContent {
+    fn add_pair(&mut self, r1: &FastqEntry, r2: &FastqEntry) {
+        for (counts, entry) in [(&mut self.r1, r1), (&mut self.r2, r2)] {
+            let seq = entry.seq.trim_end().as_bytes();
+            if counts.len() < seq.len() {
+                counts.resize(seq.len(), [0; 5]);
+            }
+            for (position, b) in counts.iter_mut().zip(seq) {
+                let i = b"ACGT".iter().position(|x| *x == b.to_ascii_uppercase()).unwrap_or(4);
+                position[i] += 1;
+            }
+        }
+    }
+
+    fn report(&self) -> String {
+        let mut report = "mate\tposition\tA\tC\tG\tT\tN\n".to_string();
+        for (mate, counts) in &[("R1", &self.r1), ("R2", &self.r2)] {
+            for (i, position) in counts.iter().enumerate() {
+                let total: u64 = position.iter().sum();
+                report = format!("{}{}\t{}", report, mate, i + 1);
+                for n in position {
+                    report = format!("{}\t{:.4}", report, *n as f64 / total as f64);
+                }
+                report.push('\n');
+            }
+        }
+        report
+    }
+}
+
+
+/// Counts of each quality character, to measure how compressible the qualities are
+struct QualHistogram {
+    counts: [u64; 256]
+}
+
+
+impl QualHistogram {
+    fn new() -> QualHistogram {
+        QualHistogram { counts: [0; 256] }
+    }
+
+    fn add(&mut self, entry: &FastqEntry) {
+        for q in entry.qual.trim_end().bytes() {
+            self.counts[q as usize] += 1;
+        }
+    }
+
+    /// Shannon entropy in bits per quality value, a lower bound on its compressed size
+    fn entropy(&self) -> f64 {
+        let total: u64 = self.counts.iter().sum();
+        self.counts.iter().filter(|n| **n > 0).map(
+            |n| {
+                let p = *n as f64 / total as f64;
+                -p * p.log2()
+            }
+        ).sum()
+    }
+}
+
+
+/// Streaming p50/p90/p99 estimates of read length and per-read mean quality, without keeping or
+/// bucketing every value
+struct QuantileStats {
+    lengths: CKMS<f64>,
+    mean_quals: CKMS<f64>
+}
+
+
+impl QuantileStats {
+    const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+    fn new() -> QuantileStats {
+        QuantileStats { lengths: CKMS::new(0.001), mean_quals: CKMS::new(0.001) }
+    }
+
+    fn add(&mut self, entry: &FastqEntry) {
+        self.lengths.insert(entry.seq_len() as f64);
+        self.mean_quals.insert(entry.mean_qual());
+    }
+
+    fn report(&self) -> String {
+        let mut report = String::new();
+        for (name, sketch) in &[("read_length", &self.lengths), ("mean_qual", &self.mean_quals)] {
+            for q in &QuantileStats::QUANTILES {
+                let value = sketch.query(*q).map_or(0.0, |(_, v)| v);
+                report.push_str(&format!("{}_p{} {:.2}\n", name, (q * 100.0).round(), value));
+            }
+        }
+        report
+    }
+}
+
+
+struct FastqHandler {
+    input_file: PathBuf,
+    output_path: PathBuf,
+    chunk_path: PathBuf,
+    chunk_size: Option<u64>,
+    chunks_written: u32,
+    chunk_records: u64,
+    filtered_path: PathBuf,
+    singleton_path: Option<PathBuf>,
+    stop_at_mate: Option<u8>,
+    multiline: bool,
+    field_delim: char,
+    wrap: Option<usize>,
+    records_read: u64,
+    reader: Box<dyn BufRead>,
+    mask: FastqEntry,
+    tile_regex: Option<Regex>,
+    buffer: Vec<u8>,
+    kept_level: u32,
+    rejected_level: u32,
+    stdout_ext: Option<&'static str>,
+    output_file: Box<dyn Output>,
+    filtered_file: Box<dyn Output>,
+    singleton_file: Option<Box<dyn Output>>
+}
+
+
+impl FastqHandler {
+    const OUTPUT_EXT: &'static str = "_filtered.fastq";
+    const FILTERED_EXT: &'static str = "_filtered_reads.fastq";
+    const SINGLETON_EXT: &'static str = "_singletons.fastq";
+
+    fn new(
+        input_file: &Path, output_file: PathBuf, filtered_file: PathBuf, append: bool, tile_regex: Option<Regex>,
+        chunk_size: Option<u64>, compression_levels: (u32, u32)
+    ) -> Result<FastqHandler> {
+        let (kept_level, rejected_level) = compression_levels;
+        let chunk_path = match chunk_size {
+            Some(_) => FastqHandler::numbered_chunk(&output_file, 1),
+            None => output_file.clone()
+        };
+
+        Ok(FastqHandler {
+            input_file: input_file.to_path_buf(),
+            output_path: output_file.clone(),
+            chunk_path: chunk_path.clone(),
+            chunk_size,
+            chunks_written: 1,
+            chunk_records: 0,
+            filtered_path: filtered_file.clone(),
+            singleton_path: None,
+            stop_at_mate: None,
+            multiline: false,
+            field_delim: ':',
+            wrap: None,
+            records_read: 0,
+            reader: codec::open_reader(input_file)?,
+            mask: FastqEntry::new(),
+            tile_regex,
+            buffer: Vec::new(),
+            kept_level,
+            rejected_level,
+            stdout_ext: None,
+            output_file: codec::open_writer(&chunk_path, append, kept_level)?,
+            filtered_file: codec::open_writer(&filtered_file, append, rejected_level)?,
+            singleton_file: None
+        })
+    }
+
+    /// Stands in for R2 with single-end input, reading no records and discarding any writes
+    fn absent() -> FastqHandler {
+        FastqHandler {
+            input_file: PathBuf::new(),
+            output_path: PathBuf::new(),
+            chunk_path: PathBuf::new(),
+            chunk_size: None,
+            chunks_written: 1,
+            chunk_records: 0,
+            filtered_path: PathBuf::new(),
+            singleton_path: None,
+            stop_at_mate: None,
+            multiline: false,
+            field_delim: ':',
+            wrap: None,
+            records_read: 0,
+            reader: Box::new(io::empty()),
+            mask: FastqEntry::new(),
+            tile_regex: None,
+            buffer: Vec::new(),
+            kept_level: 0,
+            rejected_level: 0,
+            stdout_ext: None,
+            output_file: Box::new(io::sink()),
+            filtered_file: Box::new(io::sink()),
+            singleton_file: None
+        }
+    }
+
+    /// Write stdout with the codec for this extension, where any outputs are "-". Done before
+    /// anything is written
+    fn set_stdout_ext(&mut self, stdout_ext: Option<&'static str>) -> Result<()> {
+        self.stdout_ext = stdout_ext;
+        if codec::is_stdio(&self.chunk_path) {
+            self.output_file = self.open_output(&self.chunk_path, false, self.kept_level)?;
+        }
+        if codec::is_stdio(&self.filtered_path) {
+            self.filtered_file = self.open_output(&self.filtered_path, false, self.rejected_level)?;
+        }
+        Ok(())
+    }
+
+    fn open_output(&self, output_file: &Path, append: bool, level: u32) -> io::Result<Box<dyn Output>> {
+        codec::open_writer_as(output_file, append, level, self.stdout_ext)
+    }
+
+    /// Singleton files are only opened for runs that can write to them
+    fn open_singletons(&mut self, singleton_file: PathBuf, append: bool) -> Result<()> {
+        self.singleton_file = Some(self.open_output(&singleton_file, append, self.kept_level)?);
+        self.singleton_path = Some(singleton_file);
+        Ok(())
+    }
+
+    /// Finish every output, so that what's been written is complete on disk even when compressed
+    fn finish(&mut self) -> Result<()> {
+        codec::close(&mut self.output_file)?;
+        codec::close(&mut self.filtered_file)?;
+        if let Some(f) = &mut self.singleton_file {
+            codec::close(f)?;
+        }
+        Ok(())
+    }
+
+    /// Finish every output and reopen it for appending, e.g. to checkpoint mid-run
+    fn sync_outputs(&mut self) -> Result<()> {
+        self.finish()?;
+        self.output_file = self.open_output(&self.chunk_path, true, self.kept_level)?;
+        self.filtered_file = self.open_output(&self.filtered_path, true, self.rejected_level)?;
+        if let Some(path) = &self.singleton_path {
+            self.singleton_file = Some(self.open_output(path, true, self.kept_level)?);
+        }
+        Ok(())
+    }
+
+    /// Sizes of the outputs on disk, which are only complete after `finish` or `sync_outputs`
+    fn output_sizes(&self) -> Result<Vec<(PathBuf, u64)>> {
+        let mut sizes = Vec::new();
+        for path in [Some(&self.chunk_path), Some(&self.filtered_path), self.singleton_path.as_ref()].iter().flatten() {
+            sizes.push((path.to_path_buf(), std::fs::metadata(path)?.len()));
+        }
+        Ok(sizes)
+    }
+
+    /// Reopen the input to decompress it on a background thread, and/or to read stdin with the
+    /// codec for `stdin_ext`, before any records have been read. A gzip decoder reads the header
+    /// as soon as it's opened, so stdin is reopened at most once
+    fn reopen_input(&mut self, stdin_ext: Option<&'static str>, background: bool) -> Result<()> {
+        if background {
+            self.reader = codec::open_background_reader(&self.input_file, stdin_ext)?;
+        } else if codec::is_stdio(&self.input_file) && stdin_ext.is_some() {
+            self.reader = codec::open_reader_as(&self.input_file, stdin_ext)?;
+        }
+        Ok(())
+    }
+
+    /// Have this handler and `r2` take turns reading records from this one's input, as R1 and R2
+    fn interleave(&mut self, r2: &mut FastqHandler) {
+        let reader = std::mem::replace(&mut self.reader, Box::new(io::empty()));
+        let source = Rc::new(RefCell::new(InterleavedSource { reader, pending: [VecDeque::new(), VecDeque::new()], next_mate: 0 }));
+        self.reader = Box::new(MateReader { source: Rc::clone(&source), mate: 0, record: Vec::new(), pos: 0 });
+        r2.reader = Box::new(MateReader { source, mate: 1, record: Vec::new(), pos: 0 });
+    }
+
+    /// Move to the first record of a mate in an input holding all R1 records followed by all R2,
+    /// by counting the records before it with a separate reader
+    fn seek_mate(&mut self, mate: u8) -> Result<()> {
+        let mut reader = codec::open_reader(&self.input_file)?;
+        let mut lines = [String::new(), String::new(), String::new(), String::new()];
+        let mut n_records = 0;
+        loop {
+            lines.iter_mut().for_each(String::clear);
+            let [id, seq, strand, qual] = &mut lines;
+            if let Err(e) = read_record(&mut *reader, [id, seq, strand, qual], self.multiline) {
+                return Err(FastqHandler::read_error(e, &self.input_file, n_records + 1));
+            }
+            if lines[0].is_empty() {
+                return Err(FiltererError::MalformedRecord {
+                    file: self.input_file.clone(),
+                    record: n_records,
+                    reason: format!("no records with mate number {} found", mate)
+                });
+            }
+            if mate_number(&lines[0]) == Some(mate) {
+                debug!("Mate {} starts at record {} of {:?}", mate, n_records + 1, self.input_file);
+                return self.skip_records(n_records);
+            }
+            n_records += 1;
+        }
+    }
+
+    /// Move past records that have already been processed, without parsing them
+    fn skip_records(&mut self, n_records: u64) -> Result<()> {
+        for _ in 0..n_records {
+            self.mask.clear();
+            if let Err(e) = self.read_lines() {
+                return Err(FastqHandler::read_error(e, &self.input_file, self.records_read + 1));
+            }
+            if self.is_empty() {
+                return Err(FiltererError::MalformedRecord {
+                    file: self.input_file.clone(),
+                    record: self.records_read + 1,
+                    reason: "input ended before the checkpointed position".to_string()
+                });
+            }
+            self.records_read += 1;
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.mask.id.is_empty()
+    }
+
+    fn read_entry(&mut self) -> Result<bool> {
+        self.mask.clear();
+        if let Err(e) = self.read_lines() {
+            return Err(FastqHandler::read_error(e, &self.input_file, self.records_read + 1));
+        }
+
+        if !self.is_empty() && self.stop_at_mate.is_some() && mate_number(&self.mask.id) == self.stop_at_mate {
+            self.mask.clear();
+        }
+
+        if !self.is_empty() {
+            self.mask.parse_header(self.tile_regex.as_ref(), self.field_delim);
+            self.records_read += 1;
+
+            if let Some(reason) = self.mask.structure_error() {
+                return Err(FiltererError::MalformedRecord {
+                    file: self.input_file.clone(),
+                    record: self.records_read,
+                    reason: reason.to_string()
+                });
+            }
+
+            let seq_len = self.mask.seq.trim_end().len();
+            let qual_len = self.mask.qual.trim_end().len();
+            if seq_len != qual_len {
+                return Err(FiltererError::MalformedRecord {
+                    file: self.input_file.clone(),
+                    record: self.records_read,
+                    reason: format!("sequence length {} does not match quality length {}", seq_len, qual_len)
+                });
+            }
+
+            let qual_offset = self.mask.qual_offset;
+            if let Some(q) = self.mask.qual.trim_end().bytes().find(|q| *q < qual_offset) {
+                return Err(FiltererError::MalformedRecord {
+                    file: self.input_file.clone(),
+                    record: self.records_read,
+                    reason: format!(
+                        "quality character '{}' is below the quality offset {} - is the encoding set right?",
+                        q as char, qual_offset
+                    )
+                });
+            }
+
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn read_lines(&mut self) -> io::Result<()> {
+        let mask = &mut self.mask;
+        read_record(&mut *self.reader, [&mut mask.id, &mut mask.seq, &mut mask.strand, &mut mask.qual], self.multiline)
+    }
+
+    fn read_error(e: io::Error, input_file: &Path, record: u64) -> FiltererError {
+        if e.get_ref().is_some_and(|inner| inner.is::<GzipStreamError>()) {
+            FiltererError::CorruptGzip { file: input_file.to_path_buf(), record, source: e }
+        } else {
+            FiltererError::Io(e)
+        }
+    }
+
+    /// Count the records in a fastq without opening any outputs, checking the '@' and '+' markers
+    /// of each record as it goes.
+    fn count_records(input_file: &Path, stdin_ext: Option<&str>, multiline: bool) -> Result<u64> {
+        let mut reader = codec::open_reader_as(input_file, stdin_ext)?;
+        let mut lines = [String::new(), String::new(), String::new(), String::new()];
+        let mut n_records = 0;
+        loop {
+            lines.iter_mut().for_each(String::clear);
+            let [id, seq, strand, qual] = &mut lines;
+            if let Err(e) = read_record(&mut *reader, [id, seq, strand, qual], multiline) {
+                return Err(FastqHandler::read_error(e, input_file, n_records + 1));
+            }
+            if lines[0].is_empty() {
+                return Ok(n_records);
+            }
+            if !lines[0].starts_with('@') || !lines[2].starts_with('+') || lines[3].is_empty() {
+                return Err(FiltererError::MalformedRecord {
+                    file: input_file.to_path_buf(),
+                    record: n_records + 1,
+                    reason: "missing '@' header or '+' separator".to_string()
+                });
+            }
+            n_records += 1;
+        }
+    }
+
+    fn first_header(input_file: &Path) -> Result<String> {
+        let mut reader = codec::open_reader(input_file)?;
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        Ok(header)
+    }
+
+    /// Assemble the current record into the reusable write buffer, so each record costs one
+    /// write call rather than four.
+    fn fill_buffer(&mut self) {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(self.mask.id.as_bytes());
+        match self.wrap {
+            Some(width) => FastqHandler::push_wrapped(&mut self.buffer, &self.mask.seq, width),
+            None => self.buffer.extend_from_slice(self.mask.seq.as_bytes())
+        }
+        self.buffer.extend_from_slice(self.mask.strand.as_bytes());
+        match self.wrap {
+            Some(width) => FastqHandler::push_wrapped(&mut self.buffer, &self.mask.qual, width),
+            None => self.buffer.extend_from_slice(self.mask.qual.as_bytes())
+        }
+    }
+
+    /// Add a line to the buffer split over lines of at most width characters, so that a sequence
+    /// and its qualities wrap at the same points
+    fn push_wrapped(buffer: &mut Vec<u8>, line: &str, width: usize) {
+        let content = line.trim_end().as_bytes();
+        for (i, chunk) in content.chunks(width).enumerate() {
+            if i > 0 {
+                buffer.push(b'\n');
+            }
+            buffer.extend_from_slice(chunk);
+        }
+        buffer.push(b'\n');
+    }
+
+    /// Insert a part number before the extensions of an output path, e.g. out.fastq.gz to
+    /// out_part001.fastq.gz
+    fn numbered_chunk(output_file: &Path, chunk: u32) -> PathBuf {
+        let file_name = output_file.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let (stem, exts) = file_name.split_at(file_name.find('.').unwrap_or(file_name.len()));
+        output_file.with_file_name(format!("{}_part{:03}{}", stem, chunk, exts))
+    }
+
+    /// Close the current output chunk and start writing to the next one
+    fn next_chunk(&mut self) -> Result<()> {
+        codec::close(&mut self.output_file)?;
+        self.chunks_written += 1;
+        self.chunk_records = 0;
+        self.chunk_path = FastqHandler::numbered_chunk(&self.output_path, self.chunks_written);
+        self.output_file = self.open_output(&self.chunk_path, false, self.kept_level)?;
+        Ok(())
+    }
+
+    fn output_entry(&mut self) -> Result<()> {
+        if let Some(chunk_size) = self.chunk_size {
+            if self.chunk_records == chunk_size {
+                self.next_chunk()?;
+            }
+            self.chunk_records += 1;
+        }
+        self.fill_buffer();
+        Ok(self.output_file.write_all(&self.buffer)?)
+    }
+
+    fn filter_entry(&mut self) -> Result<()> {
+        self.fill_buffer();
+        Ok(self.filtered_file.write_all(&self.buffer)?)
+    }
+
+    fn singleton_entry(&mut self) -> Result<()> {
+        self.fill_buffer();
+        match &mut self.singleton_file {
+            Some(f) => Ok(f.write_all(&self.buffer)?),
+            None => Ok(())
+        }
+    }
+
+    /// Split an input path into its directory, empty for the current one, and its file name without
+    /// the fastq extension, to name outputs after
+    fn input_dir_and_base(input_file: &Path) -> (String, String) {
+        if codec::is_stdio(input_file) {
+            return (String::new(), "stdin".to_string());
+        }
+        let input_file_slice = &*input_file.to_string_lossy();
+        let (dir, file_name) = if remote::is_remote(input_file_slice) {
+            // write outputs for remote inputs to the current directory
+            ("", input_file_slice.rsplit('/').next().unwrap_or(input_file_slice))
+        } else {
+            match input_file_slice.rfind('/') {
+                Some(i) => (&input_file_slice[..i.max(1)], &input_file_slice[i + 1..]),
+                None => ("", input_file_slice)
+            }
+        };
+        let file_name = file_name.strip_suffix(".gz").unwrap_or(file_name);
+        let base = [".fastq", ".fq"].iter().find_map(|ext| file_name.strip_suffix(ext)).unwrap_or(file_name);
+        (dir.to_string(), base.to_string())
+    }
+}
+
+
+/// The outputs written for each mate
+#[derive(Clone,Copy)]
+enum OutputKind {
+    Kept,
+    Rejected,
+    Singleton
+}
+
+
+impl OutputKind {
+    fn name(self) -> &'static str {
+        match self {
+            OutputKind::Kept => "kept",
+            OutputKind::Rejected => "rejected",
+            OutputKind::Singleton => "singleton"
+        }
+    }
+
+    fn default_ext(self) -> &'static str {
+        match self {
+            OutputKind::Kept => FastqHandler::OUTPUT_EXT,
+            OutputKind::Rejected => FastqHandler::FILTERED_EXT,
+            OutputKind::Singleton => FastqHandler::SINGLETON_EXT
+        }
+    }
+}
+
+
+/// Counts the warnings raised during a run, and under --strict turns them into errors
+struct Warnings {
+    strict: bool,
+    count: Cell<u64>
+}
+
+
+impl Warnings {
+    fn new(strict: bool) -> Warnings {
+        Warnings { strict, count: Cell::new(0) }
+    }
+
+    fn warn(&self, message: String) -> Result<()> {
+        self.count.set(self.count.get() + 1);
+        if self.strict {
+            return Err(FiltererError::Strict(message));
+        }
+        warn!("{}", message);
+        Ok(())
+    }
+}
+
+
+/// How far a run had got, and the size of each output at that point. Restoring truncates the
+/// outputs back to these sizes, discarding anything written after the checkpoint, so that resuming
+/// neither duplicates nor drops records.
+struct Checkpoint {
+    read_pairs: u64,
+    output_sizes: Vec<(PathBuf, u64)>
+}
+
+
+impl Checkpoint {
+    fn load(file_path: &Path) -> Result<Checkpoint> {
+        let f = BufReader::new(File::open(file_path)?);
+        let mut checkpoint = Checkpoint { read_pairs: 0, output_sizes: Vec::new() };
+        for line in f.lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.splitn(3, '\t').collect();
+            match fields.as_slice() {
+                ["read_pairs", n] => checkpoint.read_pairs = n.parse().map_err(|_| Checkpoint::invalid(file_path))?,
+                ["output", size, path] => checkpoint.output_sizes.push(
+                    (PathBuf::from(path), size.parse().map_err(|_| Checkpoint::invalid(file_path))?)
+                ),
+                _ => return Err(Checkpoint::invalid(file_path))
+            }
+        }
+        Ok(checkpoint)
+    }
+
+    fn invalid(file_path: &Path) -> FiltererError {
+        FiltererError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid checkpoint file {:?}", file_path)))
+    }
+
+    /// Written atomically, so a crash mid-write can't leave a truncated checkpoint
+    fn save(&self, file_path: &Path) -> Result<()> {
+        let mut report = format!("read_pairs\t{}\n", self.read_pairs);
+        for (path, size) in &self.output_sizes {
+            report.push_str(&format!("output\t{}\t{}\n", size, path.display()));
+        }
+        write_atomically(file_path, report.as_bytes())?;
+        Ok(())
+    }
+
+    fn restore_outputs(&self) -> Result<()> {
+        for (path, size) in &self.output_sizes {
+            OpenOptions::new().write(true).open(path)?.set_len(*size)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// R1 and R2 outputs for one tile
+type TileFiles = (Box<dyn Output>, Box<dyn Output>);
+
+
+/// Routes read pairs to a pair of files per tile. Only a limited number of tiles have their files
+/// open at once - when the limit is hit, another tile's files are flushed and closed, and are
+/// reopened for appending if that tile comes up again.
+struct TileSplitter {
+    output_dir: PathBuf,
+    max_open: usize,
+    append: bool,
+    compression_level: u32,
+    open_files: HashMap<String, TileFiles>,
+    seen_tiles: HashSet<String>
+}
+
+
+impl TileSplitter {
+    fn new(output_dir: &Path, max_open: usize, append: bool, compression_level: u32) -> Result<TileSplitter> {
+        std::fs::create_dir_all(output_dir)?;
+        Ok(
+            TileSplitter {
+                output_dir: output_dir.to_path_buf(),
+                max_open: max_open.max(1),
+                append,
+                compression_level,
+                open_files: HashMap::new(),
+                seen_tiles: HashSet::new()
+            }
+        )
+    }
+
+    fn tile_path(&self, tile_id: &str, mate: &str) -> PathBuf {
+        let tile_id: String = tile_id.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+        let tile_id = if tile_id.is_empty() { "unknown".to_string() } else { tile_id };
+        self.output_dir.join(format!("tile_{}_{}.fastq{}", tile_id, mate, codec::OUTPUT_SUFFIX))
+    }
+
+    fn write_pair(&mut self, tile_id: &str, r1: &[u8], r2: &[u8]) -> Result<()> {
+        if !self.open_files.contains_key(tile_id) {
+            if self.open_files.len() >= self.max_open {
+                let to_close = self.open_files.keys().next().unwrap().to_string();
+                let (f1, f2) = self.open_files.remove(&to_close).unwrap();
+                f1.finish()?;
+                f2.finish()?;
+            }
+
+            let append = self.append || self.seen_tiles.contains(tile_id);
+            let f1 = codec::open_writer(&self.tile_path(tile_id, "R1"), append, self.compression_level)?;
+            let f2 = codec::open_writer(&self.tile_path(tile_id, "R2"), append, self.compression_level)?;
+            self.open_files.insert(tile_id.to_string(), (f1, f2));
+            self.seen_tiles.insert(tile_id.to_string());
+        }
+
+        let (f1, f2) = self.open_files.get_mut(tile_id).unwrap();
+        f1.write_all(r1)?;
+        f2.write_all(r2)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for (_, (f1, f2)) in self.open_files.drain() {
+            f1.finish()?;
+            f2.finish()?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Numeric tile, tile ID, x, y and input order, so that equal keys keep their input order
+type SortKey = (u64, String, u32, u32, u64);
+type SortedPair = (SortKey, Vec<u8>, Vec<u8>);
+
+
+/// Buffers kept read pairs for --sort_by. When the buffer outgrows its limit it's sorted and
+/// spilled to a temporary file, and the spilled runs are merged once the input is finished.
+struct PairSorter {
+    by_coord: bool,
+    max_bytes: usize,
+    tmp_dir: PathBuf,
+    buffer: Vec<SortedPair>,
+    buffered_bytes: usize,
+    runs: Vec<PathBuf>,
+    n_pairs: u64
+}
+
+
+impl PairSorter {
+    fn new(sort_by: &SortBy, sort_mem: usize, tmp_dir: PathBuf) -> PairSorter {
+        PairSorter {
+            by_coord: matches!(sort_by, SortBy::Coord),
+            max_bytes: sort_mem.saturating_mul(1024 * 1024),
+            tmp_dir,
+            buffer: Vec::new(),
+            buffered_bytes: 0,
+            runs: Vec::new(),
+            n_pairs: 0
+        }
+    }
+
+    fn add(&mut self, entry: &FastqEntry, r1: &[u8], r2: &[u8]) -> Result<()> {
+        let (x, y) = if self.by_coord { (entry.x.unwrap_or(0), entry.y.unwrap_or(0)) } else { (0, 0) };
+        let key = (entry.tile_id.parse().unwrap_or(u64::MAX), entry.tile_id.clone(), x, y, self.n_pairs);
+        self.n_pairs += 1;
+
+        // a final record without a line ending can't be left to run into the next one
+        let (mut r1, mut r2) = (r1.to_vec(), r2.to_vec());
+        for record in [&mut r1, &mut r2] {
+            if record.last() != Some(&b'\n') {
+                record.push(b'\n');
+            }
+        }
+        self.buffered_bytes += r1.len() + r2.len() + key.1.len() + 64;
+        self.buffer.push((key, r1, r2));
+        if self.buffered_bytes > self.max_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        self.buffer.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let file_path = self.tmp_dir.join(format!("rustq_filterer_sort_{}_{}.tmp", std::process::id(), self.runs.len()));
+        debug!("Spilling {} read pairs to {:?}", self.buffer.len(), file_path);
+        let mut f = BufWriter::new(File::create(&file_path)?);
+        for ((tile_number, tile_id, x, y, n), r1, r2) in self.buffer.drain(..) {
+            writeln!(f, "{}\t{}\t{}\t{}\t{}", n, tile_number, x, y, tile_id)?;
+            f.write_all(&r1)?;
+            f.write_all(&r2)?;
+        }
+        f.flush()?;
+        self.runs.push(file_path);
+        self.buffered_bytes = 0;
+        Ok(())
+    }
+
+    /// Read the next pair back from a spilled run: a line with its key, then the two records
+    fn read_pair(reader: &mut dyn BufRead, file_path: &Path) -> Result<Option<SortedPair>> {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let invalid = || FiltererError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid sort run {:?}", file_path)));
+        let fields: Vec<&str> = line.trim_end_matches('\n').splitn(5, '\t').collect();
+        if fields.len() != 5 {
+            return Err(invalid());
+        }
+        let key = (
+            fields[1].parse().map_err(|_| invalid())?, fields[4].to_string(), fields[2].parse().map_err(|_| invalid())?,
+            fields[3].parse().map_err(|_| invalid())?, fields[0].parse().map_err(|_| invalid())?
+        );
+
+        let mut records = [Vec::new(), Vec::new()];
+        for record in records.iter_mut() {
+            for _ in 0..4 {
+                reader.read_until(b'\n', record)?;
+            }
+        }
+        let [r1, r2] = records;
+        Ok(Some((key, r1, r2)))
+    }
+
+    /// Write every buffered and spilled pair out in order, removing the spilled runs
+    fn finish(&mut self, f1: &mut dyn Write, f2: &mut dyn Write) -> Result<()> {
+        if self.runs.is_empty() {
+            self.buffer.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            for (_, r1, r2) in self.buffer.drain(..) {
+                f1.write_all(&r1)?;
+                f2.write_all(&r2)?;
+            }
+            return Ok(());
+        }
+
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        info!("Merging {} sorted runs", self.runs.len());
+        let mut readers = Vec::new();
+        for file_path in &self.runs {
+            readers.push(BufReader::new(File::open(file_path)?));
+        }
+        let mut heap = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some((key, r1, r2)) = PairSorter::read_pair(reader, &self.runs[i])? {
+                heap.push(Reverse((key, i, r1, r2)));
+            }
+        }
+        while let Some(Reverse((_, i, r1, r2))) = heap.pop() {
+            f1.write_all(&r1)?;
+            f2.write_all(&r2)?;
+            if let Some((key, r1, r2)) = PairSorter::read_pair(&mut readers[i], &self.runs[i])? {
+                heap.push(Reverse((key, i, r1, r2)));
+            }
+        }
+        for file_path in self.runs.drain(..) {
+            std::fs::remove_file(file_path)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Counters from a completed run
+#[derive(Debug,Default,Serialize)]
+pub struct Stats {
+    pub read_pairs_checked: i64,
+    pub read_pairs_removed: i64,
+    pub read_pairs_remaining: i64,
+    pub r1_too_short: i64,
+    pub r2_too_short: i64,
+    pub both_too_short: i64,
+    pub r1_too_long: i64,
+    pub r2_too_long: i64,
+    pub both_too_long: i64,
+    pub unparseable_tile_pairs_removed: i64,
+    pub id_prefix_pairs_removed: i64,
+    pub region_pairs_removed: i64,
+    pub contaminant_pairs_removed: i64,
+    pub unequal_length_pairs_removed: i64,
+    pub mate_number_pairs_removed: i64,
+    pub rc_identical_pairs_removed: i64,
+    pub malformed_pairs_skipped: i64,
+    pub mismatched_id_pairs: i64,
+    pub empty_read_pairs: i64,
+    pub singletons_written: i64,
+    pub trailing_n_bases_trimmed: i64,
+    pub read_pairs_merged: i64,
+    pub r1_min_len: usize,
+    pub r1_max_len: usize,
+    pub r1_mean_len: f64,
+    pub r2_min_len: usize,
+    pub r2_max_len: usize,
+    pub r2_mean_len: f64,
+    /// Lengths of kept reads after any trimming
+    pub r1_trimmed_min_len: usize,
+    pub r1_trimmed_max_len: usize,
+    pub r1_trimmed_mean_len: f64,
+    pub r2_trimmed_min_len: usize,
+    pub r2_trimmed_max_len: usize,
+    pub r2_trimmed_mean_len: f64,
+    pub total_bases: u64,
+    pub q30_bases: u64,
+    pub warnings: u64,
+    pub inputs_swapped: bool,
+    /// Removed pairs by the criterion they failed. A pair failing several criteria counts towards
+    /// each of them, so these can add up to more than read_pairs_removed
+    pub removed_by: BTreeMap<String, i64>
+}
+
+
+struct FastqPairChecker<'a> {
+    args: &'a Cli,
+    warnings: Warnings,
+    r1: FastqHandler,
+    r2: FastqHandler,
+    inputs_swapped: bool,
+    criteria: Vec<Box<dyn Criterion>>,
+    custom_filters: Vec<Box<dyn PairFilter>>,
+    tile_splitter: Option<TileSplitter>,
+    sorter: Option<PairSorter>,
+    annotation_file: Option<BufWriter<File>>,
+    read_pairs_checked: i64,
+    read_pairs_removed: i64,
+    read_pairs_remaining: i64,
+    removed_by: HashMap<&'static str, i64>,
+    fastp_removed: HashMap<&'static str, i64>,
+    malformed_pairs_skipped: i64,
+    mismatched_id_pairs: i64,
+    empty_read_pairs: i64,
+    singletons_written: i64,
+    tileless_pairs: i64,
+    trailing_n_bases_trimmed: i64,
+    r1_lengths: LengthStats,
+    r2_lengths: LengthStats,
+    r1_trimmed_lengths: LengthStats,
+    r2_trimmed_lengths: LengthStats,
+    quantiles: Option<QuantileStats>,
+    last_checkpoint: u64,
+    last_stats_flush: i64,
+    bases_before: BaseCounts,
+    bases_after: BaseCounts,
+    lane_counts: HashMap<String, u64>,
+    base_content: Option<BaseContent>,
+    rng: StdRng,
+    qual_bin_tables: Option<([u8; 256], [u8; 256])>,
+    qual_binning_stats: Option<(QualHistogram, QualHistogram)>,
+    rejected_pairs_written: i64,
+    merged_file: Option<Box<dyn Output>>,
+    read_pairs_merged: i64
+}
+
+
+impl<'a> FastqPairChecker <'a>{
+    fn new(args: &'a Cli) -> Result<FastqPairChecker<'a>> {
+        let warnings = Warnings::new(args.strict);
+        FastqPairChecker::check_stdio(args)?;
+        let inputs_swapped = FastqPairChecker::check_swapped_inputs(args, &warnings)?;
+        FastqPairChecker::log_config(args, inputs_swapped);
+        FastqPairChecker::check_distinct_paths(args, inputs_swapped)?;
+        if let Some((_, value)) = args.quality_bins.iter().find(|(_, value)| args.bin_quality && *value > args.max_qual) {
+            return Err(FiltererError::Config(format!("quality bin value {} is above --max_qual {}", value, args.max_qual)));
+        }
+        FastqPairChecker::check_single_end(args)?;
+        let mut criteria: Vec<Box<dyn Criterion>> = vec![
+            Box::new(criteria::Length::new(args.thresholds(), args.max_length, args.single_end()))
+        ];
+
+        if args.drop_unparseable_tiles {
+            criteria.push(Box::new(criteria::UnparseableTiles::default()));
+        }
+
+        if args.removes_tiles() {
+            let mut remove_tiles = args.remove_tiles.clone();
+            if let Some(file_path) = &args.remove_tiles_file {
+                remove_tiles.extend(criteria::Tiles::read_file(file_path)?);
+            }
+            let tiles = criteria::Tiles::new(&remove_tiles, false);
+            if tiles.is_empty() {
+                warnings.warn("No tiles given to remove, so no tiles will be removed".to_string())?;
+            } else {
+                criteria.push(Box::new(tiles));
+            }
+        }
+
+        if !args.keep_tiles.is_empty() {
+            let tiles = criteria::Tiles::new(&args.keep_tiles, true);
+            if tiles.is_empty() {
+                warnings.warn("No tiles given in --keep_tiles, so every read pair will be removed".to_string())?;
+            }
+            criteria.push(Box::new(tiles));
+        }
+
+        if let Some(file_path) = &args.remove_reads {
+            let read_ids = criteria::ReadIds::from_file(file_path, false)?;
+            if read_ids.is_empty() {
+                warnings.warn(format!("No read IDs in {:?}, so no reads will be removed by ID", file_path))?;
+            } else {
+                criteria.push(Box::new(read_ids));
+            }
+        }
+
+        if let Some(file_path) = &args.extract_reads {
+            criteria.push(Box::new(criteria::ReadIds::from_file(file_path, true)?));
+        }
+
+        if !args.remove_id_prefixes.is_empty() {
+            criteria.push(Box::new(criteria::IdPrefixes::new(&args.remove_id_prefixes)));
+        }
+
+        if let Some(file_path) = &args.remove_regions {
+            criteria.push(Box::new(criteria::Regions::from_file(file_path)?));
+        }
+
+        if !args.contaminant_seqs.is_empty() {
+            criteria.push(Box::new(criteria::Contaminants::new(&args.contaminant_seqs, args.contaminant_mismatches)?));
+        }
+
+        let max_ambiguous_frac = if args.remove_ambiguous { Some(0.0) } else { args.max_ambiguous_frac };
+        if let Some(max_frac) = max_ambiguous_frac {
+            criteria.push(Box::new(criteria::Ambiguous::new(max_frac)));
+        }
+
+        if let Some(max_frac) = args.max_n_fraction {
+            criteria.push(Box::new(criteria::NContent::new(max_frac, args.trim_r1, args.trim_r2)));
+        }
+
+        if !args.min_qual_at_positions.is_empty() {
+            criteria.push(Box::new(criteria::QualAtPositions::new(&args.min_qual_at_positions)));
+        }
+
+        if let Some(min_qual) = args.min_mean_qual {
+            criteria.push(Box::new(criteria::MeanQual::new(min_qual)));
+        }
+
+        if let Some(min_frac) = args.min_q30_frac {
+            criteria.push(Box::new(criteria::Q30::new(min_frac)));
+        }
+
+        if let Some(min_qual) = args.min_anchor_qual {
+            criteria.push(Box::new(criteria::AnchorQual::new(min_qual, args.anchor_len)));
+        }
+
+        if let Some(min_bases) = args.min_distinct_bases {
+            criteria.push(Box::new(criteria::DistinctBases::new(min_bases)));
+        }
+
+        if let Some(max_run) = args.max_internal_n_run {
+            criteria.push(Box::new(criteria::InternalNRun::new(max_run)));
+        }
+
+        if args.remove_rc_identical {
+            criteria.push(Box::new(criteria::RcIdentical::new(args.rc_mismatches)));
+        }
+
+        let listed = |name: &str| args.filters.iter().any(|f| f == name);
+        if args.require_equal_mate_length || listed("mate_length") {
+            criteria.push(Box::new(criteria::MateLength::default()));
+        }
+
+        if args.require_mate_number || listed("mate_number") {
+            criteria.push(Box::new(criteria::MateNumber::default()));
+        }
+
+        if !args.filters.is_empty() {
+            criteria = FastqPairChecker::select_criteria(criteria, &args.filters)?;
+        }
+
+        if args.passthrough {
+            debug!("Passthrough mode - not applying any criteria");
+            criteria.clear();
+        }
+
+        if args.sort_by.is_some() {
+            // logged rather than counted as a run warning, so that --strict runs can still sort
+            warn!("--sort_by disables streaming: kept reads are only written once all input has been read");
+        }
+
+        let annotation_file = match &args.annotation_file {
+            Some(file_path) => {
+                let mut f = BufWriter::new(File::create(file_path)?);
+                f.write_all(b"read_id\tlength_r1\tlength_r2\tmean_qual\tstatus\tfailed_criteria\n")?;
+                Some(f)
+            },
+            None => None
+        };
+
+        let (i1, i2) = args.inputs();
+        let (i1, i2) = if inputs_swapped { (i2, i1) } else { (i1, i2) };
+
+        let mut resume_from = None;
+        if args.resume {
+            let file_path = args.checkpoint_file.as_ref().unwrap();
+            let checkpoint = Checkpoint::load(file_path)?;
+            info!("Resuming from {:?} after {} read pairs", file_path, checkpoint.read_pairs);
+            checkpoint.restore_outputs()?;
+            resume_from = Some(checkpoint.read_pairs);
+        }
+
+        let append = args.append || args.resume;
+        let mut r1 = FastqHandler::new(
+            i1, args.output_path(1, OutputKind::Kept, inputs_swapped),
+            args.output_path(1, OutputKind::Rejected, inputs_swapped), append,
+            args.tile_regex.clone(), args.chunk_size, args.compression_levels()
+        )?;
+        let mut r2 = if args.single_end() {
+            FastqHandler::absent()
+        } else {
+            FastqHandler::new(
+                i2, args.output_path(2, OutputKind::Kept, inputs_swapped),
+                args.output_path(2, OutputKind::Rejected, inputs_swapped), append,
+                args.tile_regex.clone(), args.chunk_size, args.compression_levels()
+            )?
+        };
+        let (stdin_ext, stdout_ext) = args.stdio_exts();
+        r1.set_stdout_ext(stdout_ext)?;
+        r2.set_stdout_ext(stdout_ext)?;
+        // each thread beyond the main one decompresses an input, R1 first
+        let threads = args.threads();
+        info!("Using {} thread(s)", threads);
+        r1.reopen_input(stdin_ext, threads > 1)?;
+        if args.interleaved {
+            r1.interleave(&mut r2);
+        } else {
+            r2.reopen_input(stdin_ext, threads > 2 && !args.single_end())?;
+        }
+        r1.multiline = args.multiline;
+        r2.multiline = args.multiline;
+        r1.field_delim = args.field_delim;
+        r2.field_delim = args.field_delim;
+        r1.wrap = args.wrap;
+        r2.wrap = args.wrap;
+        let (offset_1, offset_2) = args.qual_offsets();
+        r1.mask.qual_offset = offset_1;
+        r2.mask.qual_offset = offset_2;
+        if args.concatenated_mates {
+            r1.stop_at_mate = Some(2);
+            r2.seek_mate(2)?;
+        }
+        if args.writes_singletons() {
+            r1.open_singletons(args.output_path(1, OutputKind::Singleton, inputs_swapped), append)?;
+            if !args.single_end() {
+                r2.open_singletons(args.output_path(2, OutputKind::Singleton, inputs_swapped), append)?;
+            }
+        }
+        if let Some(read_pairs) = resume_from {
+            if args.interleaved {
+                // one pair at a time, so that R2 records skipped over by R1 don't pile up
+                for _ in 0..read_pairs {
+                    r1.skip_records(1)?;
+                    r2.skip_records(1)?;
+                }
+            } else {
+                r1.skip_records(read_pairs)?;
+                if !args.single_end() {
+                    r2.skip_records(read_pairs)?;
+                }
+            }
+        }
+
+        Ok(FastqPairChecker {
+            args,
+            warnings,
+            r1,
+            r2,
+            inputs_swapped,
+            criteria,
+            custom_filters: Vec::new(),
+            tile_splitter: match &args.split_by_tile {
+                Some(dir) => Some(TileSplitter::new(dir, args.max_open_tiles, args.append, args.compression_levels().0)?),
+                None => None
+            },
+            sorter: args.sort_by.as_ref().map(|sort_by| PairSorter::new(sort_by, args.sort_mem, args.tmp_dir())),
+            annotation_file,
+            read_pairs_checked: 0,
+            read_pairs_removed: 0,
+            read_pairs_remaining: 0,
+            removed_by: HashMap::new(),
+            fastp_removed: HashMap::new(),
+            malformed_pairs_skipped: 0,
+            mismatched_id_pairs: 0,
+            empty_read_pairs: 0,
+            singletons_written: 0,
+            tileless_pairs: 0,
+            trailing_n_bases_trimmed: 0,
+            r1_lengths: LengthStats::default(),
+            r2_lengths: LengthStats::default(),
+            r1_trimmed_lengths: LengthStats::default(),
+            r2_trimmed_lengths: LengthStats::default(),
+            quantiles: args.stats_file.as_ref().map(|_| QuantileStats::new()),
+            last_checkpoint: 0,
+            last_stats_flush: 0,
+            bases_before: BaseCounts::default(),
+            bases_after: BaseCounts::default(),
+            lane_counts: HashMap::new(),
+            base_content: args.base_content_file.as_ref().map(|_| BaseContent::default()),
+            rng: args.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+            qual_bin_tables: if args.bin_quality {
+                let (offset_1, offset_2) = args.qual_offsets();
+                Some((quality_bin_table(&args.quality_bins, offset_1), quality_bin_table(&args.quality_bins, offset_2)))
+            } else {
+                None
+            },
+            qual_binning_stats: match (args.bin_quality, &args.stats_file) {
+                (true, Some(_)) => Some((QualHistogram::new(), QualHistogram::new())),
+                _ => None
+            },
+            rejected_pairs_written: 0,
+            merged_file: match (&args.merged_out, args.merge) {
+                (Some(file_path), true) => Some(codec::open_writer(file_path, append, args.compression_levels().0)?),
+                _ => None
+            },
+            read_pairs_merged: 0
+        })
+    }
+
+    /// Pick out the criteria named in --filters, in that order
+    fn select_criteria(mut criteria: Vec<Box<dyn Criterion>>, filters: &[String]) -> Result<Vec<Box<dyn Criterion>>> {
+        let mut selected = Vec::new();
+        for name in filters {
+            match criteria.iter().position(|c| c.name() == name) {
+                Some(i) => selected.push(criteria.remove(i)),
+                None => return Err(FiltererError::Config(
+                    format!("criterion '{}' is in --filters more than once, or its options aren't set", name)
+                ))
+            }
+        }
+        debug!("Running criteria: {:?}", filters);
+        Ok(selected)
+    }
+
+    /// Log every effective parameter, including defaults and the output paths inferred from the
+    /// inputs, to help with troubleshooting
+    fn log_config(args: &Cli, swapped: bool) {
+        if !log_enabled!(Level::Debug) {
+            return;
+        }
+        debug!("Effective parameters: {}", serde_json::to_string(args).unwrap_or_default());
+        for mate in 1..=args.mates() {
+            debug!(
+                "R{} outputs: kept {:?}, rejected {:?}", mate,
+                args.output_path(mate, OutputKind::Kept, swapped), args.output_path(mate, OutputKind::Rejected, swapped)
+            );
+            if args.writes_singletons() {
+                debug!("R{} singletons: {:?}", mate, args.output_path(mate, OutputKind::Singleton, swapped));
+            }
+        }
+    }
+
+    /// Refuse to run if any two outputs, or an output and an input, are the same file, before any
+    /// output is opened and truncated
+    fn check_distinct_paths(args: &Cli, swapped: bool) -> Result<()> {
+        let (i1, i2) = args.inputs();
+        let mut outputs = Vec::new();
+        for mate in 1..=args.mates() {
+            outputs.push((format!("R{} output", mate), args.output_path(mate, OutputKind::Kept, swapped)));
+            outputs.push((format!("R{} filtered", mate), args.output_path(mate, OutputKind::Rejected, swapped)));
+            if args.writes_singletons() {
+                outputs.push((format!("R{} singleton", mate), args.output_path(mate, OutputKind::Singleton, swapped)));
+            }
+        }
+        let other_files = [
+            ("stats", &args.stats_file), ("annotation", &args.annotation_file),
+            ("lane counts", &args.lane_counts_file), ("base content", &args.base_content_file),
+            ("checkpoint", &args.checkpoint_file), ("merged", &args.merged_out)
+        ];
+        for (name, file_path) in other_files {
+            if let Some(file_path) = file_path {
+                outputs.push((name.to_string(), file_path.to_path_buf()));
+            }
+        }
+
+        let comparable = |p: &Path| p.canonicalize().unwrap_or_else(
+            |_| std::env::current_dir().map(|d| d.join(p)).unwrap_or_else(|_| p.to_path_buf())
+        );
+        let mut seen: Vec<(String, PathBuf)> = Vec::new();
+        for &(mate, input_file) in [("R1", i1), ("R2", i2)].iter().take(args.mates().into()) {
+            if !input_file.to_str().is_some_and(remote::is_remote) && !codec::is_stdio(input_file) {
+                seen.push((format!("{} input", mate), comparable(input_file)));
+            }
+        }
+        for (name, file_path) in outputs {
+            let path = comparable(&file_path);
+            if let Some((other, _)) = seen.iter().find(|(_, p)| *p == path) {
+                return Err(FiltererError::Config(format!("{} file {:?} is the same as the {} file", name, file_path, other)));
+            }
+            seen.push((name, path));
+        }
+        Ok(())
+    }
+
+    /// Refuse options that only make sense for pairs when there's no R2
+    fn check_single_end(args: &Cli) -> Result<()> {
+        if !args.single_end() {
+            return Ok(());
+        }
+        let r2_options = [
+            ("--o2", args.o2.is_some()), ("--f2", args.f2.is_some()), ("--s2", args.s2.is_some()),
+            ("--merge", args.merge), ("--remove_rc_identical", args.remove_rc_identical),
+            ("--require_mate_number", args.require_mate_number), ("--pad_shorter_mate", args.pad_shorter_mate),
+            ("--threshold_r2", args.threshold_r2.is_some()),
+            ("--split_by_tile", args.split_by_tile.is_some())
+        ];
+        match r2_options.iter().find(|(_, given)| *given) {
+            Some((option, _)) => Err(FiltererError::Config(format!("{} needs paired input, but no --i2 was given", option))),
+            None => Ok(())
+        }
+    }
+
+    /// Refuse to read stdin or write stdout for options that need to go back over an input or
+    /// output, or with more than one input on stdin
+    fn check_stdio(args: &Cli) -> Result<()> {
+        if (args.input_gzip || args.output_gzip) && !codec::supports("gz") {
+            return Err(FiltererError::Config("--input_gzip and --output_gzip need a build with gzip support".to_string()));
+        }
+        let (i1, i2) = args.inputs();
+        let stdin = codec::is_stdio(i1) || codec::is_stdio(i2);
+        if codec::is_stdio(i1) && args.i2.as_deref().is_some_and(codec::is_stdio) {
+            return Err(FiltererError::Config(
+                "--i1 and --i2 can't both be read from stdin - use --interleaved for both mates in one input".to_string()
+            ));
+        }
+        if stdin && args.concatenated_mates {
+            return Err(FiltererError::Config("--concatenated_mates can't read from stdin, as it reads --i1 twice".to_string()));
+        }
+
+        let kinds = [OutputKind::Kept, OutputKind::Rejected, OutputKind::Singleton];
+        let stdout = (1..=args.mates()).any(|mate| kinds.iter().any(|kind| codec::is_stdio(&args.output_path(mate, *kind, false))));
+        let seeking_options = [
+            ("--checkpoint_file", args.checkpoint_file.is_some()), ("--resume", args.resume),
+            ("--chunk_size", args.chunk_size.is_some())
+        ];
+        match seeking_options.iter().find(|(_, given)| *given) {
+            Some((option, _)) if stdout => Err(FiltererError::Config(format!("{} can't be used with outputs to stdout", option))),
+            _ => Ok(())
+        }
+    }
+
+    /// Look at the mate numbers of the first record in each input, warning if R1 looks like R2 and
+    /// vice versa. Returns whether the inputs should be swapped, i.e. if --fix_swapped is set.
+    fn check_swapped_inputs(args: &Cli, warnings: &Warnings) -> Result<bool> {
+        let (i1, i2) = args.inputs();
+        // reading the first header from stdin would lose it
+        if args.single_end() || codec::is_stdio(i1) || codec::is_stdio(i2) {
+            return Ok(false);
+        }
+        let mate_1 = FastqHandler::first_header(i1).ok().and_then(|h| mate_number(&h));
+        let mate_2 = FastqHandler::first_header(i2).ok().and_then(|h| mate_number(&h));
+        if mate_1 == Some(2) && mate_2 == Some(1) {
+            if args.fix_swapped {
+                info!("--i1 {:?} looks like R2 and --i2 {:?} like R1 - swapping them", i1, i2);
+                return Ok(true);
+            }
+            warnings.warn(
+                format!("--i1 {:?} looks like R2 and --i2 {:?} like R1 - use --fix_swapped to swap them", i1, i2)
+            )?;
+        }
+        Ok(false)
+    }
+
+    /// Run every criterion, returning the names of those the current pair failed. All criteria
+    /// are run so that each one's own counters stay complete. Single-end reads are passed as both
+    /// mates, so that checks on either mate apply to them.
+    fn check_reads(&self) -> Vec<&'static str> {
+        let r2 = if self.args.single_end() { &self.r1.mask } else { &self.r2.mask };
+        let mut failed = Vec::new();
+        for criterion in &self.criteria {
+            if criterion.evaluate(&self.r1.mask, r2) == CheckResult::Fail {
+                failed.push(criterion.name());
+            }
+        }
+        for filter in &self.custom_filters {
+            if !filter.keep(&self.r1.mask, r2) {
+                failed.push(filter.name());
+            }
+        }
+        failed
+    }
+
+    /// Count a removed pair towards every criterion it failed, and towards the fastp category of
+    /// the first one
+    fn count_removal(&mut self, failed: &[&'static str]) {
+        self.read_pairs_removed += 1;
+        for name in failed {
+            *self.removed_by.entry(name).or_insert(0) += 1;
+        }
+        if let Some(first) = failed.first() {
+            let category = self.fastp_category(first);
+            *self.fastp_removed.entry(category).or_insert(0) += 1;
+        }
+    }
+
+    /// Which of fastp's filtering_result categories a pair removed by a criterion falls in. Failing
+    /// the length check is too short if either mate is, else too long
+    fn fastp_category(&self, criterion: &str) -> &'static str {
+        match criterion {
+            "empty_read" => "too_short_reads",
+            "length" => {
+                let (threshold_1, threshold_2) = self.args.thresholds();
+                let too_short = self.r1.mask.seq_len() < threshold_1 ||
+                    (!self.args.single_end() && self.r2.mask.seq_len() < threshold_2);
+                if too_short { "too_short_reads" } else { "too_long_reads" }
+            },
+            "ambiguous" | "internal_n" | "n_content" => "too_many_N_reads",
+            _ => "low_quality_reads"
+        }
+    }
+
+    /// Removed pairs by criterion, including enabled criteria that haven't removed anything
+    fn removed_by(&self) -> BTreeMap<&'static str, i64> {
+        let mut removed_by: BTreeMap<&'static str, i64> = self.criteria.iter().map(|c| (c.name(), 0))
+            .chain(self.custom_filters.iter().map(|f| (f.name(), 0)))
+            .collect();
+        for (name, count) in &self.removed_by {
+            *removed_by.entry(name).or_insert(0) += count;
+        }
+        removed_by
+    }
+
+    /// Total of a named counter across all criteria, or 0 if none of them keep it
+    fn criterion_count(&self, counter: &str) -> i64 {
+        self.criteria.iter().flat_map(|c| c.counts()).filter(|(name, _)| *name == counter).map(|(_, n)| n).sum()
+    }
+
+    fn annotate(&mut self, failed: &[&str]) -> Result<()> {
+        if let Some(f) = &mut self.annotation_file {
+            let (r1, r2) = (&self.r1.mask, &self.r2.mask);
+            let n_bases = r1.qual.trim_end().len() + r2.qual.trim_end().len();
+            let qual_sum: u64 = r1.qual_scores().chain(r2.qual_scores()).map(u64::from).sum();
+            let mean_qual = if n_bases == 0 { 0.0 } else { qual_sum as f64 / n_bases as f64 };
+            let r2_len = if self.args.single_end() { "NA".to_string() } else { r2.seq_len().to_string() };
+
+            writeln!(
+                f, "{}\t{}\t{}\t{:.2}\t{}\t{}",
+                r1.read_id.trim_start_matches('@'), r1.seq_len(), r2_len, mean_qual,
+                if failed.is_empty() { "passed" } else { "failed" },
+                if failed.is_empty() { "-".to_string() } else { failed.join(",") }
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Make sure the current R1 and R2 records are mates, i.e. that the inputs are in step
+    fn check_read_ids(&mut self) -> Result<()> {
+        if self.args.single_end() || reads_are_paired(&self.r1.mask.read_id, &self.r2.mask.read_id) {
+            return Ok(());
+        }
+        let error = FiltererError::MismatchedPair {
+            record: self.r1.records_read,
+            r1_id: self.r1.mask.read_id.clone(),
+            r2_id: self.r2.mask.read_id.clone()
+        };
+        if !self.args.allow_unpaired {
+            return Err(error);
+        }
+        self.mismatched_id_pairs += 1;
+        if self.mismatched_id_pairs == 1 {
+            self.warnings.warn(format!("{} - further mismatches are only counted", error))?;
+        }
+        Ok(())
+    }
+
+    /// Pairs with an empty mate skip the criteria entirely. Under --on_empty_read singleton the
+    /// other mate is rescued to the singletons file, provided it passes the length threshold.
+    fn handle_empty_read(&mut self, r1_empty: bool, r2_empty: bool) -> Result<()> {
+        self.empty_read_pairs += 1;
+        self.count_removal(&["empty_read"]);
+        self.annotate(&["empty_read"])?;
+
+        let singletons = self.args.on_empty_read == OnEmptyRead::Singleton;
+        let write_rejected = self.sample_rejected();
+        let (threshold_1, threshold_2) = self.args.thresholds();
+        for (handler, empty, threshold) in [(&mut self.r1, r1_empty, threshold_1), (&mut self.r2, r2_empty, threshold_2)] {
+            if singletons && !empty && handler.mask.seq_len() >= threshold {
+                handler.singleton_entry()?;
+                self.singletons_written += 1;
+            } else if write_rejected {
+                handler.filter_entry()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Once one input has run out, write the current and remaining records of the other to its
+    /// singletons file
+    fn pad_shorter_mate(&mut self, r1_left: bool) -> Result<()> {
+        let handler = if r1_left { &mut self.r1 } else { &mut self.r2 };
+        let mut n_records = 0;
+        loop {
+            handler.singleton_entry()?;
+            n_records += 1;
+            if !handler.read_entry()? {
+                break;
+            }
+        }
+        info!("Wrote {} records left over in {:?} to its singletons file", n_records, handler.input_file);
+        self.singletons_written += n_records;
+        Ok(())
+    }
+
+    /// Whether to write the current removed pair to the filtered files under --reject_sample_frac
+    fn sample_rejected(&mut self) -> bool {
+        let write = match self.args.reject_sample_frac {
+            Some(frac) => self.rng.gen::<f64>() < frac,
+            None => true
+        };
+        if write {
+            self.rejected_pairs_written += 1;
+        }
+        write
+    }
+
+    /// Record the parameters that produced each output in a JSON file alongside it
+    fn write_params_files(&self) -> Result<()> {
+        let params = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "command_line": std::env::args().collect::<Vec<String>>(),
+            "params": self.args
+        });
+        for handler in [&self.r1, &self.r2].iter().take(self.args.mates().into()) {
+            if codec::is_stdio(&handler.output_path) {
+                continue;
+            }
+            let mut file_path = handler.output_path.clone().into_os_string();
+            file_path.push(".params.json");
+            let f = File::create(file_path)?;
+            serde_json::to_writer_pretty(f, &params).map_err(io::Error::from)?;
+        }
+        Ok(())
+    }
+
+    fn write_lane_counts(&self) -> Result<()> {
+        if let Some(file_path) = &self.args.lane_counts_file {
+            let mut lanes: Vec<(&String, &u64)> = self.lane_counts.iter().collect();
+            lanes.sort();
+            let mut report = "lane\tread_pairs\n".to_string();
+            for (lane, count) in lanes {
+                let lane = if lane.is_empty() { "unknown" } else { lane };
+                report = format!("{}{}\t{}\n", report, lane, count);
+            }
+            std::fs::write(file_path, report)?;
+        }
+        Ok(())
+    }
+
+    fn save_checkpoint(&mut self, file_path: &Path) -> Result<()> {
+        self.r1.sync_outputs()?;
+        if !self.args.single_end() {
+            self.r2.sync_outputs()?;
+        }
+        if let (Some(f), Some(path)) = (&mut self.merged_file, &self.args.merged_out) {
+            codec::close(f)?;
+            *f = codec::open_writer(path, true, self.args.compression_levels().0)?;
+        }
+        self.write_checkpoint(file_path)
+    }
+
+    /// Write out anything held back and finish every output, so that errors from the last writes,
+    /// e.g. a full disk, fail the run rather than being lost when the outputs are dropped
+    fn finish_outputs(&mut self) -> Result<()> {
+        if let Some(splitter) = &mut self.tile_splitter {
+            splitter.finish()?;
+        }
+        if let Some(sorter) = &mut self.sorter {
+            sorter.finish(&mut *self.r1.output_file, &mut *self.r2.output_file)?;
+        }
+        self.r1.finish()?;
+        self.r2.finish()?;
+        if let Some(f) = &mut self.merged_file {
+            codec::close(f)?;
+        }
+        if let Some(f) = &mut self.annotation_file {
+            f.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Record the read pairs processed so far and the size of each output, which must be complete
+    /// on disk
+    fn write_checkpoint(&mut self, file_path: &Path) -> Result<()> {
+        let mut output_sizes = self.r1.output_sizes()?;
+        if !self.args.single_end() {
+            output_sizes.extend(self.r2.output_sizes()?);
+        }
+        if let (Some(_), Some(path)) = (&self.merged_file, &self.args.merged_out) {
+            output_sizes.push((path.to_path_buf(), std::fs::metadata(path)?.len()));
+        }
+        let checkpoint = Checkpoint { read_pairs: self.r1.records_read, output_sizes };
+        checkpoint.save(file_path)?;
+        self.last_checkpoint = checkpoint.read_pairs;
+        debug!("Checkpointed after {} read pairs", checkpoint.read_pairs);
+        Ok(())
+    }
+
+    /// Guard against a threshold that can never be met, e.g. --threshold 150 on 100bp reads
+    fn check_threshold(&self) -> Result<()> {
+        let (threshold_1, threshold_2) = self.args.thresholds();
+        let mut mates = vec![(1, threshold_1, self.r1.mask.seq_len())];
+        if !self.args.single_end() {
+            mates.push((2, threshold_2, self.r2.mask.seq_len()));
+        }
+        for (mate, threshold, read_len) in mates {
+            if threshold > read_len {
+                self.warnings.warn(format!(
+                    "R{} length threshold {} is longer than the first R{} ({}bp) - most reads will probably be filtered",
+                    mate, threshold, mate, read_len
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The parts of a fastp JSON report that MultiQC's fastp module reads. As in fastp, each
+    /// removed read counts in one category, by the first criterion it failed: too short or too long
+    /// for the length check, too many N for N or other ambiguous bases, and low quality otherwise
+    fn write_fastp_json(&self, file_path: &Path, status: &str) -> Result<()> {
+        let mates = self.args.mates();
+        let removed = |category: &str| self.fastp_removed.get(category).copied().unwrap_or(0) * i64::from(mates);
+        let report = serde_json::json!({
+            "summary": {
+                "before_filtering": self.bases_before.fastp_summary(mates),
+                "after_filtering": self.bases_after.fastp_summary(mates)
+            },
+            "filtering_result": {
+                "passed_filter_reads": self.read_pairs_remaining * i64::from(mates),
+                "low_quality_reads": removed("low_quality_reads"),
+                "too_many_N_reads": removed("too_many_N_reads"),
+                "too_short_reads": removed("too_short_reads"),
+                "too_long_reads": removed("too_long_reads")
+            },
+            "status": status
+        });
+        let report = serde_json::to_vec_pretty(&report).map_err(io::Error::from)?;
+        write_atomically(file_path, &report)?;
+        Ok(())
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            read_pairs_checked: self.read_pairs_checked,
+            read_pairs_removed: self.read_pairs_removed,
+            read_pairs_remaining: self.read_pairs_remaining,
+            r1_too_short: self.criterion_count("r1_too_short"),
+            r2_too_short: self.criterion_count("r2_too_short"),
+            both_too_short: self.criterion_count("both_too_short"),
+            r1_too_long: self.criterion_count("r1_too_long"),
+            r2_too_long: self.criterion_count("r2_too_long"),
+            both_too_long: self.criterion_count("both_too_long"),
+            unparseable_tile_pairs_removed: self.criterion_count("unparseable_tile_pairs_removed"),
+            id_prefix_pairs_removed: self.criterion_count("id_prefix_pairs_removed"),
+            region_pairs_removed: self.criterion_count("region_pairs_removed"),
+            contaminant_pairs_removed: self.criterion_count("contaminant_pairs_removed"),
+            unequal_length_pairs_removed: self.criterion_count("unequal_length_pairs_removed"),
+            mate_number_pairs_removed: self.criterion_count("mate_number_pairs_removed"),
+            rc_identical_pairs_removed: self.criterion_count("rc_identical_pairs_removed"),
+            malformed_pairs_skipped: self.malformed_pairs_skipped,
+            mismatched_id_pairs: self.mismatched_id_pairs,
+            empty_read_pairs: self.empty_read_pairs,
+            singletons_written: self.singletons_written,
+            trailing_n_bases_trimmed: self.trailing_n_bases_trimmed,
+            read_pairs_merged: self.read_pairs_merged,
+            r1_min_len: self.r1_lengths.min,
+            r1_max_len: self.r1_lengths.max,
+            r1_mean_len: self.r1_lengths.mean(),
+            r2_min_len: self.r2_lengths.min,
+            r2_max_len: self.r2_lengths.max,
+            r2_mean_len: self.r2_lengths.mean(),
+            r1_trimmed_min_len: self.r1_trimmed_lengths.min,
+            r1_trimmed_max_len: self.r1_trimmed_lengths.max,
+            r1_trimmed_mean_len: self.r1_trimmed_lengths.mean(),
+            r2_trimmed_min_len: self.r2_trimmed_lengths.min,
+            r2_trimmed_max_len: self.r2_trimmed_lengths.max,
+            r2_trimmed_mean_len: self.r2_trimmed_lengths.mean(),
+            total_bases: self.bases_before.total_bases(),
+            q30_bases: self.bases_before.q30_bases,
+            warnings: self.warnings.count.get(),
+            inputs_swapped: self.inputs_swapped,
+            removed_by: self.removed_by().into_iter().map(|(name, count)| (name.to_string(), count)).collect()
+        }
+    }
+
+    /// The stats file as a JSON object of the input and output paths, the filtering options and
+    /// the counters returned by `filter`. Single-end runs leave out the R2 fields
+    fn write_json_stats(&self, file_path: &Path, status: &str) -> Result<()> {
+        let single_end = self.args.single_end();
+        let mut remove_tiles: Vec<String> = self.args.remove_tiles.iter().map(|t| normalise_tile(t)).collect();
+        remove_tiles.sort();
+        remove_tiles.dedup();
+        let mut report = serde_json::Map::new();
+        let (i1, i2) = self.args.inputs();
+        for (mate, input_file) in [(1, i1), (2, i2)].iter().take(self.args.mates().into()) {
+            report.insert(format!("r{}i", mate), serde_json::json!(input_file));
+            report.insert(format!("r{}o", mate), serde_json::json!(self.args.output_path(*mate, OutputKind::Kept, self.inputs_swapped)));
+            report.insert(format!("r{}f", mate), serde_json::json!(self.args.output_path(*mate, OutputKind::Rejected, self.inputs_swapped)));
+        }
+        report.insert("filter_threshold".to_string(), serde_json::json!(self.args.len_threshold));
+        report.insert("threshold_r1".to_string(), serde_json::json!(self.args.threshold_r1));
+        if !single_end {
+            report.insert("threshold_r2".to_string(), serde_json::json!(self.args.threshold_r2));
+        }
+        report.insert("remove_tiles".to_string(), serde_json::json!(remove_tiles));
+        report.insert("remove_tiles_file".to_string(), serde_json::json!(self.args.remove_tiles_file));
+        report.insert("remove_reads".to_string(), serde_json::json!(self.args.remove_reads));
+        if let serde_json::Value::Object(stats) = serde_json::to_value(self.stats()).map_err(io::Error::from)? {
+            for (name, value) in stats {
+                if !(single_end && (name.starts_with("r2_") || name.starts_with("both_"))) {
+                    report.insert(name, value);
+                }
+            }
+        }
+        report.insert("status".to_string(), serde_json::json!(status));
+        let report = serde_json::to_vec_pretty(&report).map_err(io::Error::from)?;
+        write_atomically(file_path, &report)?;
+        Ok(())
+    }
+
+    /// Write the stats file, which can be done mid-run with `finished` false
+    fn write_stats_file(&self, finished: bool) -> Result<()> {
+        let status = if finished { "finished" } else { "running" };
+        match (&self.args.stats_file, &self.args.stats_format) {
+            (Some(file_path), StatsFormat::Json) => return self.write_json_stats(file_path, status),
+            (Some(file_path), StatsFormat::FastpJson) => return self.write_fastp_json(file_path, status),
+            _ => {}
+        }
+
+        if let Some(file_path) = &self.args.stats_file {
+            // single-end runs leave out the R2 lines
+            let single_end = self.args.single_end();
+            // breakdowns of what happened to read pairs, which --stats_only_changed leaves out when zero
+            let only_changed = self.args.stats_only_changed;
+            let counter = |report: &mut String, name: &str, value: i64| {
+                if value != 0 || !only_changed {
+                    report.push_str(&format!("{} {}\n", name, value));
+                }
+            };
+            let mut report = format!("r1i {:?}\nr1o {:?}\nr1f {:?}\n", self.args.inputs().0, self.args.o1, self.args.f1);
+            if !single_end {
+                report = format!("{}r2i {:?}\nr2o {:?}\nr2f {:?}\n", report, self.args.inputs().1, self.args.o2, self.args.f2);
+            }
+            report = format!(
+                "{}read_pairs_checked {}\nread_pairs_removed {}\nread_pairs_remaining {}\nfilter_threshold {}\n",
+                report, self.read_pairs_checked, self.read_pairs_removed, self.read_pairs_remaining, self.args.len_threshold
+            );
+            counter(&mut report, "r1_too_short", self.criterion_count("r1_too_short"));
+            if !single_end {
+                counter(&mut report, "r2_too_short", self.criterion_count("r2_too_short"));
+                counter(&mut report, "both_too_short", self.criterion_count("both_too_short"));
+            }
+            if let Some(threshold) = self.args.threshold_r1 {
+                report = format!("{}threshold_r1 {}\n", report, threshold);
+            }
+            if let Some(threshold) = self.args.threshold_r2 {
+                report = format!("{}threshold_r2 {}\n", report, threshold);
+            }
+            report = format!(
+                "{}r1_min_len {}\nr1_max_len {}\nr1_mean_len {:.2}\n",
+                report, self.r1_lengths.min, self.r1_lengths.max, self.r1_lengths.mean()
+            );
+            if !single_end {
+                report = format!(
+                    "{}r2_min_len {}\nr2_max_len {}\nr2_mean_len {:.2}\n",
+                    report, self.r2_lengths.min, self.r2_lengths.max, self.r2_lengths.mean()
+                );
+            }
+            report = format!(
+                "{}r1_trimmed_min_len {}\nr1_trimmed_max_len {}\nr1_trimmed_mean_len {:.2}\n",
+                report, self.r1_trimmed_lengths.min, self.r1_trimmed_lengths.max, self.r1_trimmed_lengths.mean()
+            );
+            if !single_end {
+                report = format!(
+                    "{}r2_trimmed_min_len {}\nr2_trimmed_max_len {}\nr2_trimmed_mean_len {:.2}\n",
+                    report, self.r2_trimmed_lengths.min, self.r2_trimmed_lengths.max, self.r2_trimmed_lengths.mean()
+                );
+            }
+
+            if let Some(max_length) = self.args.max_length {
+                report = format!("{}max_length {}\n", report, max_length);
+                counter(&mut report, "r1_too_long", self.criterion_count("r1_too_long"));
+                if !single_end {
+                    counter(&mut report, "r2_too_long", self.criterion_count("r2_too_long"));
+                    counter(&mut report, "both_too_long", self.criterion_count("both_too_long"));
+                }
+            }
+
+            if let Some(chunk_size) = self.args.chunk_size {
+                report = format!("{}chunk_size {}\noutput_chunks {}\n", report, chunk_size, self.r1.chunks_written);
+            }
+
+            if !self.args.remove_tiles.is_empty() {
+                let mut rm_tiles: Vec<String> = self.args.remove_tiles.iter().map(|t| normalise_tile(t)).collect();
+                rm_tiles.sort();
+                rm_tiles.dedup();
+                report = format!("{}remove_tiles {:?}\n", report, rm_tiles);
+            }
+
+            if let Some(file_path) = &self.args.remove_tiles_file {
+                report = format!("{}remove_tiles_file {:?}\n", report, file_path.to_str());
+            }
+
+            if !self.args.keep_tiles.is_empty() {
+                let mut keep_tiles: Vec<String> = self.args.keep_tiles.iter().map(|t| normalise_tile(t)).collect();
+                keep_tiles.sort();
+                keep_tiles.dedup();
+                report = format!("{}keep_tiles {:?}\n", report, keep_tiles);
+            }
+
+            if !self.args.remove_id_prefixes.is_empty() {
+                report = format!("{}remove_id_prefixes {:?}\n", report, self.args.remove_id_prefixes);
+                counter(&mut report, "id_prefix_pairs_removed", self.criterion_count("id_prefix_pairs_removed"));
+            }
+
+            if self.args.drop_unparseable_tiles {
+                counter(&mut report, "unparseable_tile_pairs_removed", self.criterion_count("unparseable_tile_pairs_removed"));
+            }
+
+            if let Some(quantiles) = &self.quantiles {
+                report.push_str(&quantiles.report());
+            }
+
+            let bases = &self.bases_before;
+            report = format!(
+                "{}total_bases {}\nq30_bases {}\nq30_fraction {:.4}\n",
+                report, bases.total_bases(), bases.q30_bases, bases.fraction(bases.q30_bases)
+            );
+
+            counter(&mut report, "warnings", self.warnings.count.get() as i64);
+
+            counter(&mut report, "empty_read_pairs", self.empty_read_pairs);
+            if self.args.writes_singletons() {
+                counter(&mut report, "singletons_written", self.singletons_written);
+            }
+
+            if self.args.trim_trailing_n {
+                counter(&mut report, "trailing_n_bases_trimmed", self.trailing_n_bases_trimmed);
+            }
+
+            if let Some((before, after)) = &self.qual_binning_stats {
+                report = format!(
+                    "{}qual_bits_per_base_before_binning {:.3}\nqual_bits_per_base_after_binning {:.3}\n",
+                    report, before.entropy(), after.entropy()
+                );
+            }
+
+            if self.args.merge {
+                let merge_rate = if self.read_pairs_remaining == 0 {
+                    0.0
+                } else {
+                    self.read_pairs_merged as f64 / self.read_pairs_remaining as f64
+                };
+                counter(&mut report, "read_pairs_merged", self.read_pairs_merged);
+                report = format!("{}merge_rate {:.4}\n", report, merge_rate);
+            }
+
+            if let Some(frac) = self.args.reject_sample_frac {
+                report = format!("{}reject_sample_frac {}\n", report, frac);
+                counter(&mut report, "rejected_pairs_written", self.rejected_pairs_written);
+            }
+
+            if self.args.skip_malformed {
+                counter(&mut report, "malformed_pairs_skipped", self.malformed_pairs_skipped);
+            }
+
+            if self.args.allow_unpaired {
+                counter(&mut report, "mismatched_id_pairs", self.mismatched_id_pairs);
+            }
+
+            if self.inputs_swapped {
+                report = format!("{}inputs_swapped true\n", report);
+            }
+
+            if let Some(file_path) = &self.args.remove_reads {
+                report = format!("{}remove_reads {:?}\n", report, file_path.to_str());
+            }
+
+            if let Some(file_path) = &self.args.extract_reads {
+                report = format!("{}extract_reads {:?}\n", report, file_path.to_str());
+            }
+
+            if let Some(file_path) = &self.args.remove_regions {
+                report = format!("{}remove_regions {:?}\n", report, file_path.to_str());
+                counter(&mut report, "region_pairs_removed", self.criterion_count("region_pairs_removed"));
+            }
+
+            if self.args.require_equal_mate_length {
+                counter(&mut report, "unequal_length_pairs_removed", self.criterion_count("unequal_length_pairs_removed"));
+            }
+
+            if self.args.require_mate_number {
+                counter(&mut report, "mate_number_pairs_removed", self.criterion_count("mate_number_pairs_removed"));
+            }
+
+            if self.args.remove_rc_identical {
+                counter(&mut report, "rc_identical_pairs_removed", self.criterion_count("rc_identical_pairs_removed"));
+            }
+
+            if !self.args.contaminant_seqs.is_empty() {
+                report = format!("{}contaminant_seqs {:?}\n", report, self.args.contaminant_seqs);
+                counter(&mut report, "contaminant_pairs_removed", self.criterion_count("contaminant_pairs_removed"));
+            }
+
+            // a pair failing several criteria counts towards each one
+            for (name, count) in self.removed_by() {
+                counter(&mut report, &format!("removed_by_{}", name), count);
+            }
+
+            if finished && self.input_empty() {
+                report = format!("{}note the input contained no read pairs\n", report);
+            }
+
+            report = format!("{}status {}\n", report, status);
+            write_atomically(file_path, report.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Whether the inputs held no records at all, as opposed to every read pair being removed
+    fn input_empty(&self) -> bool {
+        self.r1.records_read == 0 && self.r2.records_read == 0
+    }
+
+    /// Under --merge, try to write the current pair as one merged read, returning whether it was
+    fn merge_pair(&mut self) -> Result<bool> {
+        let f = match &mut self.merged_file {
+            Some(f) => f,
+            None => return Ok(false)
+        };
+        let merged = merge_mates(
+            &self.r1.mask, &self.r2.mask, self.args.min_overlap, self.args.max_overlap_mismatch_frac, self.args.max_qual
+        );
+        match merged {
+            Some((seq, qual)) => {
+                write!(f, "{}\n{}\n+\n{}\n", self.r1.mask.header(), seq, qual)?;
+                self.read_pairs_merged += 1;
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+
+    /// Write the current pair to the per-tile files, the sort buffer or the output files
+    fn output_pair(&mut self) -> Result<()> {
+        if let Some(splitter) = &mut self.tile_splitter {
+            self.r1.fill_buffer();
+            self.r2.fill_buffer();
+            splitter.write_pair(&self.r1.mask.tile_id, &self.r1.buffer, &self.r2.buffer)?;
+        } else if let Some(sorter) = &mut self.sorter {
+            self.r1.fill_buffer();
+            self.r2.fill_buffer();
+            sorter.add(&self.r1.mask, &self.r1.buffer, &self.r2.buffer)?;
+        } else {
+            self.r1.output_entry()?;
+            self.r2.output_entry()?;
+        }
+        Ok(())
+    }
+
+    /// Apply the requested changes to the headers, sequences and qualities of a kept pair
+    fn transform_kept_pair(&mut self) {
+        if let Some(bases) = self.args.trim_r1 {
+            self.r1.mask.hard_trim(bases);
+        }
+        if let Some(bases) = self.args.trim_r2 {
+            self.r2.mask.hard_trim(bases);
+        }
+        if self.args.strip_header_comment {
+            self.r1.mask.strip_header_comment(self.args.keep_mate_number);
+            self.r2.mask.strip_header_comment(self.args.keep_mate_number);
+        }
+        if let Some(min_qual) = self.args.mask_below_qual {
+            self.r1.mask.mask_below_qual(min_qual);
+            self.r2.mask.mask_below_qual(min_qual);
+        }
+        if let Some(shift) = self.args.qual_shift {
+            self.r1.mask.shift_qual(shift, self.args.max_qual);
+            self.r2.mask.shift_qual(shift, self.args.max_qual);
+        }
+        if let Some((table_1, table_2)) = &self.qual_bin_tables {
+            if let Some((before, _)) = &mut self.qual_binning_stats {
+                before.add(&self.r1.mask);
+                before.add(&self.r2.mask);
+            }
+            self.r1.mask.bin_qual(table_1);
+            self.r2.mask.bin_qual(table_2);
+            if let Some((_, after)) = &mut self.qual_binning_stats {
+                after.add(&self.r1.mask);
+                after.add(&self.r2.mask);
+            }
+        }
+    }
+
+    fn run(&mut self) -> Result<Stats> {
+        info!("Starting");
+        if self.args.embed_params_header {
+            self.write_params_files()?;
+        }
+        self.last_checkpoint = self.r1.records_read;
+        loop {
+            if let Some(file_path) = &self.args.checkpoint_file {
+                if self.r1.records_read >= self.last_checkpoint + self.args.checkpoint_interval {
+                    self.save_checkpoint(file_path)?;
+                }
+            }
+            if let Some(interval) = self.args.stats_flush_interval {
+                if self.read_pairs_checked - self.last_stats_flush >= interval as i64 {
+                    self.write_stats_file(false)?;
+                    self.last_stats_flush = self.read_pairs_checked;
+                }
+            }
+
+            let (read_1, read_2) = match (self.r1.read_entry(), self.r2.read_entry()) {
+                (Ok(read_1), Ok(read_2)) => (read_1, if self.args.single_end() { read_1 } else { read_2 }),
+                (Err(e), _) | (_, Err(e)) => {
+                    if self.args.skip_malformed && matches!(e, FiltererError::MalformedRecord { .. }) {
+                        self.warnings.warn(format!("Skipping read pair: {}", e))?;
+                        self.malformed_pairs_skipped += 1;
+                        continue;
+                    }
+                    return Err(e);
+                }
+            };
+
+            if read_1 && read_2 {
+                self.read_pairs_checked += 1;
+                self.check_read_ids()?;
+                if self.read_pairs_checked == 1 {
+                    self.check_threshold()?;
+                }
+                if self.r1.mask.tile_id.is_empty() && !self.args.drop_unparseable_tiles &&
+                    (self.args.removes_tiles() || self.args.split_by_tile.is_some()) {
+                    self.tileless_pairs += 1;
+                }
+                if self.args.lane_counts_file.is_some() {
+                    *self.lane_counts.entry(self.r1.mask.lane.clone()).or_default() += 1;
+                }
+                if let Some(base_content) = &mut self.base_content {
+                    base_content.add_pair(&self.r1.mask, &self.r2.mask);
+                }
+                self.r1_lengths.add(self.r1.mask.seq_len());
+                if !self.args.single_end() {
+                    self.r2_lengths.add(self.r2.mask.seq_len());
+                }
+                if let Some(quantiles) = &mut self.quantiles {
+                    quantiles.add(&self.r1.mask);
+                    if !self.args.single_end() {
+                        quantiles.add(&self.r2.mask);
+                    }
+                }
+                if self.args.stats_file.is_some() {
+                    self.bases_before.add_pair(&self.r1.mask, &self.r2.mask);
+                }
+
+                if self.args.trim_trailing_n && !self.args.passthrough {
+                    let n_trimmed = self.r1.mask.trim_trailing_n() + self.r2.mask.trim_trailing_n();
+                    self.trailing_n_bases_trimmed += n_trimmed as i64;
+                }
+
+                let r1_empty = self.r1.mask.seq_len() == 0;
+                let r2_empty = if self.args.single_end() { r1_empty } else { self.r2.mask.seq_len() == 0 };
+                if (r1_empty || r2_empty) && !self.args.passthrough {
+                    self.handle_empty_read(r1_empty, r2_empty)?;
+                    continue;
+                }
+
+                let failed = self.check_reads();
+                self.annotate(&failed)?;
+                if !failed.is_empty() {
+                    self.count_removal(&failed);
+                    if self.args.tag_instead_of_filter {
+                        let tag = format!(" FAIL:{}", failed.join(","));
+                        self.r1.mask.append_to_header(&tag);
+                        self.r2.mask.append_to_header(&tag);
+                        self.output_pair()?;
+                    } else if self.sample_rejected() {
+                        self.r1.filter_entry()?;
+                        self.r2.filter_entry()?;
+                    }
+                } else {
+                    self.read_pairs_remaining += 1;
+                    if !self.args.passthrough {
+                        self.transform_kept_pair();
+                    }
+                    self.r1_trimmed_lengths.add(self.r1.mask.seq_len());
+                    if !self.args.single_end() {
+                        self.r2_trimmed_lengths.add(self.r2.mask.seq_len());
+                    }
+                    if self.args.stats_file.is_some() {
+                        self.bases_after.add_pair(&self.r1.mask, &self.r2.mask);
+                    }
+                    if !self.merge_pair()? {
+                        self.output_pair()?;
+                    }
+                }
+            } else {
+                if read_1 != read_2 && self.args.pad_shorter_mate {
+                    self.pad_shorter_mate(read_1)?;
+                } else if read_1 != read_2 && self.args.interleaved {
+                    return Err(FiltererError::MalformedRecord {
+                        file: self.r1.input_file.clone(),
+                        record: self.r1.records_read + self.r2.records_read,
+                        reason: "interleaved input has an odd number of records, so the last has no mate".to_string()
+                    });
+                } else if read_1 != read_2 {
+                    let (longer, shorter) = if read_1 { (&self.r1, &self.r2) } else { (&self.r2, &self.r1) };
+                    return Err(FiltererError::MalformedRecord {
+                        file: longer.input_file.clone(),
+                        record: longer.records_read,
+                        reason: format!(
+                            "{:?} ended after {} records, but this input has more - are the inputs out of sync?",
+                            shorter.input_file, shorter.records_read
+                        )
+                    });
+                }
+                info!("Finished");
+                break
+            }
+        }
+        self.finish_outputs()?;
+        if let Some(file_path) = &self.args.checkpoint_file {
+            self.write_checkpoint(file_path)?;
+        }
+        if self.tileless_pairs > 0 {
+            self.warnings.warn(format!(
+                "{} read pairs had no parseable tile ID, so were kept without tile filtering", self.tileless_pairs
+            ))?;
+        }
+        if self.input_empty() {
+            let (i1, i2) = self.args.inputs();
+            let inputs = if self.args.single_end() { format!("{:?}", i1) } else { format!("{:?} and {:?}", i1, i2) };
+            self.warnings.warn(format!("No read pairs found in {} - the input is empty", inputs))?;
+        }
+        self.write_lane_counts()?;
+        if let (Some(file_path), Some(base_content)) = (&self.args.base_content_file, &self.base_content) {
+            std::fs::write(file_path, base_content.report())?;
+        }
+        self.write_stats_file(true)?;
+        Ok(self.stats())
+    }
+}
+
+
+/// Filter a pair of fastqs, returning the counters that are also written to --stats_file
+pub fn filter(args: &Cli) -> Result<Stats> {
+    let mut info = FastqPairChecker::new(args)?;
+    info.run()
+}
+
+
+/// Filter as with `filter`, also removing read pairs that any of `filters` doesn't keep. These run
+/// after the built-in criteria, and aren't affected by --filters. Under --passthrough they're
+/// ignored like the built-in ones. Single-end reads are passed to them as both mates
+pub fn filter_with(args: &Cli, filters: Vec<Box<dyn PairFilter>>) -> Result<Stats> {
+    let mut info = FastqPairChecker::new(args)?;
+    if !args.passthrough {
+        info.custom_filters = filters;
+    }
+    info.run()
+}
+
+
+/// Run whichever mode the command line asks for
+pub fn run_filterer(args: &Cli) -> Result<()> {
+    if args.selftest {
+        return selftest::run(&args.tmp_dir());
+    }
+
+    if let Some(header) = &args.parse_test {
+        let mut entry = FastqEntry::new();
+        entry.id = format!("{}\n", header);
+        entry.parse_header(args.tile_regex.as_ref(), args.field_delim);
+        println!(
+            "read_id\t{}\ntile\t{}\nlane\t{}\nx\t{}\ny\t{}",
+            entry.read_id, entry.tile_id, entry.lane,
+            entry.x.map_or("-".to_string(), |x| x.to_string()), entry.y.map_or("-".to_string(), |y| y.to_string())
+        );
+        return Ok(());
+    }
+
+    if args.count_only {
+        let (i1, i2) = args.inputs();
+        let input_files = if args.concatenated_mates || args.interleaved || args.single_end() {
+            vec![i1]
+        } else {
+            vec![i1, i2]
+        };
+        for input_file in input_files {
+            println!("{}\t{}", input_file.display(), FastqHandler::count_records(input_file, args.stdio_exts().0, args.multiline)?);
+        }
+        return Ok(());
+    }
+
+    filter(args)?;
+    Ok(())
+}
+
+
+/// Set up logging, highlighting warnings and errors when colour is enabled
+pub fn init_logging(args: &Cli) {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let write_style = match args.color {
+        ColorChoice::Always => WriteStyle::Always,
+        ColorChoice::Never => WriteStyle::Never,
+        ColorChoice::Auto if no_color => WriteStyle::Never,
+        ColorChoice::Auto => WriteStyle::Auto
+    };
+
+    // warnings are shown unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .write_style(write_style)
+        .format(
+            |buf, record| {
+                let mut style = buf.style();
+                match record.level() {
+                    Level::Error => { style.set_color(Color::Red).set_bold(true); },
+                    Level::Warn => { style.set_color(Color::Yellow); },
+                    _ => {}
+                }
+                writeln!(
+                    buf, "[{} {:<5} {}] {}",
+                    buf.timestamp(), style.value(record.level()), record.target(), style.value(record.args())
+                )
+            }
+        )
+        .init();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for a test's inputs and outputs
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustq_filterer_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn run_args(argv: &[&str]) -> Cli {
+        Cli::from_iter_safe(std::iter::once("rustq_filterer").chain(argv.iter().cloned())).unwrap()
+    }
+
+    /// Flips the lowest bit of every byte, standing in for a real codec
+    struct FlipBits<T>(T);
+
+    impl<R: io::Read> io::Read for FlipBits<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.read(buf)?;
+            buf[..n].iter_mut().for_each(|b| *b ^= 1);
+            Ok(n)
+        }
+    }
+
+    impl<W: Write> Write for FlipBits<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let flipped: Vec<u8> = buf.iter().map(|b| b ^ 1).collect();
+            self.0.write_all(&flipped)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl<W: Write> Output for FlipBits<W> {
+        fn finish(mut self: Box<Self>) -> io::Result<()> {
+            self.flush()
+        }
+    }
+
+    fn flip_bits(data: &[u8]) -> Vec<u8> {
+        data.iter().map(|b| b ^ 1).collect()
+    }
+
+    /// Records for one mate of read pairs numbered from `first`, every third one too short to keep
+    fn mate_records(mate: u8, first: usize, n_pairs: usize) -> String {
+        (first..first + n_pairs).map(|i| {
+            let len = if i % 3 == 0 { 20 } else { 40 };
+            format!("@M1:1:FC:1:1101:{}:2000 {}:N:0:A\n{}\n+\n{}\n", i, mate, "ACGT".repeat(len / 4), "I".repeat(len))
+        }).collect()
+    }
+
+    /// Decompressed contents of an output
+    fn read_output(file_path: &Path) -> Vec<u8> {
+        let mut content = Vec::new();
+        io::Read::read_to_end(&mut codec::open_reader(file_path).unwrap(), &mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn hard_trim_cuts_from_either_end_and_keeps_line_endings() {
+        let trimmed = |bases: i32| {
+            let mut entry = FastqEntry::new();
+            entry.seq = String::from("ACGTAC\n");
+            entry.qual = String::from("ABCDEF\r\n");
+            entry.hard_trim(bases);
+            (entry.seq, entry.qual)
+        };
+        assert_eq!(trimmed(2), (String::from("GTAC\n"), String::from("CDEF\r\n")));
+        assert_eq!(trimmed(-2), (String::from("ACGT\n"), String::from("ABCD\r\n")));
+        assert_eq!(trimmed(0), (String::from("ACGTAC\n"), String::from("ABCDEF\r\n")));
+        assert_eq!(trimmed(10), (String::from("\n"), String::from("\r\n")));
+        assert_eq!(trimmed(-10), (String::from("\n"), String::from("\r\n")));
+    }
+
+    #[test]
+    fn seeded_sampling_gives_the_same_output_with_any_thread_count() {
+        let dir = test_dir("seeded_threads");
+        for mate in [1, 2] {
+            let records: String = (0..500).map(|i| {
+                let len = if i % 3 == 0 { 20 } else { 50 };
+                format!("@M1:1:FC:1:1101:{}:2000 {}:N:0:A\n{}\n+\n{}\n", i, mate, "ACGT".repeat(len / 4), "I".repeat(len / 4 * 4))
+            }).collect();
+            std::fs::write(dir.join(format!("in_R{}.fastq", mate)), records).unwrap();
+        }
+        let outputs = |threads: &str| {
+            let path = |name: &str| dir.join(format!("{}_{}.fastq", name, threads)).to_str().unwrap().to_string();
+            let (o1, o2, f1, f2) = (path("o1"), path("o2"), path("f1"), path("f2"));
+            let (i1, i2) = (dir.join("in_R1.fastq"), dir.join("in_R2.fastq"));
+            let args = run_args(&[
+                "--i1", i1.to_str().unwrap(), "--i2", i2.to_str().unwrap(), "--o1", &o1, "--o2", &o2,
+                "--f1", &f1, "--f2", &f2, "--reject_sample_frac", "0.5", "--seed", "1", "--threads", threads
+            ]);
+            filter(&args).unwrap();
+            [o1, o2, f1, f2].iter().map(|f| std::fs::read(f).unwrap()).collect::<Vec<_>>()
+        };
+        let single_threaded = outputs("1");
+        assert!(!single_threaded[2].is_empty());
+        assert_eq!(single_threaded, outputs("2"));
+        assert_eq!(single_threaded, outputs("3"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn inputs_of_different_lengths_are_an_error() {
+        let dir = test_dir("unequal_inputs");
+        for (mate, n_records) in [(1, 10), (2, 20)] {
+            let records: String = (0..n_records).map(|i| {
+                format!("@M1:1:FC:1:1101:{}:2000 {}:N:0:A\n{}\n+\n{}\n", i, mate, "A".repeat(50), "I".repeat(50))
+            }).collect();
+            std::fs::write(dir.join(format!("in_R{}.fastq", mate)), records).unwrap();
+        }
+        let (i1, i2) = (dir.join("in_R1.fastq"), dir.join("in_R2.fastq"));
+        let args = run_args(&["--i1", i1.to_str().unwrap(), "--i2", i2.to_str().unwrap()]);
+        match filter(&args) {
+            Err(FiltererError::MalformedRecord { file, record, .. }) => {
+                assert_eq!((file, record), (i2.clone(), 11));
+            },
+            other => panic!("Expected a MalformedRecord error, got {:?}", other.map(|_| ()))
+        }
+
+        let s1 = dir.join("s1.fastq");
+        let s2 = dir.join("s2.fastq");
+        let args = run_args(&[
+            "--i1", i1.to_str().unwrap(), "--i2", i2.to_str().unwrap(), "--pad_shorter_mate",
+            "--s1", s1.to_str().unwrap(), "--s2", s2.to_str().unwrap()
+        ]);
+        let stats = filter(&args).unwrap();
+        assert_eq!(stats.read_pairs_checked, 10);
+        assert_eq!(std::fs::read_to_string(&s2).unwrap().lines().count(), 40);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fastp_json_counts_each_removed_read_once() {
+        let dir = test_dir("fastp_json");
+        for mate in [1, 2] {
+            let records: String = (0..10).map(|i| {
+                let seq = if i % 2 == 0 { "NNNNNNNN".to_string() } else { "ACGT".repeat(10) };
+                format!("@M1:1:FC:1:1101:{}:2000 {}:N:0:A\n{}\n+\n{}\n", i, mate, seq, "I".repeat(seq.len()))
+            }).collect();
+            std::fs::write(dir.join(format!("in_R{}.fastq", mate)), records).unwrap();
+        }
+        let (i1, i2, stats_file) = (dir.join("in_R1.fastq"), dir.join("in_R2.fastq"), dir.join("fastp.json"));
+        let args = run_args(&[
+            "--i1", i1.to_str().unwrap(), "--i2", i2.to_str().unwrap(), "--threshold", "10",
+            "--max_n_fraction", "0.5", "--stats_file", stats_file.to_str().unwrap(), "--stats_format", "fastp_json"
+        ]);
+        filter(&args).unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&stats_file).unwrap()).unwrap();
+        let result = &report["filtering_result"];
+        assert_eq!(result["passed_filter_reads"], 10);
+        assert_eq!(result["too_short_reads"], 10);
+        assert_eq!(result["too_many_N_reads"], 0);
+        assert_eq!(result["low_quality_reads"], 0);
+        assert_eq!(result["too_long_reads"], 0);
+        assert_eq!(report["summary"]["before_filtering"]["total_reads"], 20);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn registered_codec_reads_and_writes_its_extension() {
+        register_codec(
+            "flip",
+            |inner| Box::new(BufReader::new(FlipBits(inner))),
+            |inner, _level| Box::new(FlipBits(BufWriter::new(inner)))
+        );
+        let dir = test_dir("registered_codec");
+        let mut records = Vec::new();
+        for mate in [1, 2] {
+            let record = format!("@M1:1:FC:1:1101:1000:2000 {}:N:0:A\n{}\n+\n{}\n", mate, "ACGT".repeat(10), "I".repeat(40));
+            std::fs::write(dir.join(format!("in_R{}.fastq.flip", mate)), flip_bits(record.as_bytes())).unwrap();
+            records.push(record);
+        }
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let args = run_args(&[
+            "--i1", &path("in_R1.fastq.flip"), "--i2", &path("in_R2.fastq.flip"),
+            "--o1", &path("out_R1.fastq.flip"), "--o2", &path("out_R2.fastq.flip"),
+            "--f1", &path("rejected_R1.fastq"), "--f2", &path("rejected_R2.fastq")
+        ]);
+        let stats = filter(&args).unwrap();
+        assert_eq!(stats.read_pairs_remaining, 1);
+        for (mate, record) in records.iter().enumerate() {
+            let output = std::fs::read(dir.join(format!("out_R{}.fastq.flip", mate + 1))).unwrap();
+            assert_eq!(flip_bits(&output), record.as_bytes());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn output_template_expands_every_placeholder() {
+        let args = run_args(&[
+            "--i1", "data/sample_R1.fastq.gz", "--i2", "data/sample_R2.fastq.gz",
+            "--output_template", "{dir}/{base}.{mate}.{kind}.fastq{gz}"
+        ]);
+        for (kind, name) in [(OutputKind::Kept, "kept"), (OutputKind::Rejected, "rejected"), (OutputKind::Singleton, "singleton")] {
+            assert_eq!(
+                args.output_path(2, kind, false),
+                PathBuf::from(format!("data/sample_R2.R2.{}.fastq{}", name, codec::OUTPUT_SUFFIX))
+            );
+        }
+        assert!(Cli::from_iter_safe(&["rustq_filterer", "--i1", "a.fastq", "--output_template", "{base}.{zip}"]).is_err());
+    }
+
+    #[test]
+    fn trimmed_lengths_are_taken_from_kept_reads_after_trimming() {
+        let dir = test_dir("trimmed_lengths");
+        for mate in [1, 2] {
+            let records: String = [50, 46, 20].iter().enumerate().map(|(i, len)| {
+                let seq = format!("{}NN", &"ACGT".repeat(*len / 4 + 1)[..*len - 2]);
+                format!("@M1:1:FC:1:1101:{}:2000 {}:N:0:A\n{}\n+\n{}\n", i, mate, seq, "I".repeat(*len))
+            }).collect();
+            std::fs::write(dir.join(format!("in_R{}.fastq", mate)), records).unwrap();
+        }
+        let (i1, i2) = (dir.join("in_R1.fastq"), dir.join("in_R2.fastq"));
+        let args = run_args(&[
+            "--i1", i1.to_str().unwrap(), "--i2", i2.to_str().unwrap(), "--trim_trailing_n", "--trim_r1", "5",
+            "--trim_r2", "-10"
+        ]);
+        let stats = filter(&args).unwrap();
+        assert_eq!((stats.r1_min_len, stats.r1_max_len), (20, 50));
+        assert_eq!((stats.r1_trimmed_min_len, stats.r1_trimmed_max_len, stats.r1_trimmed_mean_len), (39, 43, 41.0));
+        assert_eq!((stats.r2_trimmed_min_len, stats.r2_trimmed_max_len, stats.r2_trimmed_mean_len), (34, 38, 36.0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn quality_below_the_offset_is_malformed() {
+        let dir = test_dir("qual_offset");
+        let i1 = dir.join("in.fastq");
+        std::fs::write(&i1, "@r1\nACGT\n+\nhhhh\n@r2\nACGT\n+\nhh#h\n").unwrap();
+        let args = run_args(&["--i1", i1.to_str().unwrap(), "--qual_offset", "64", "--threshold", "1"]);
+        match filter(&args) {
+            Err(FiltererError::MalformedRecord { file, record, reason }) => {
+                assert_eq!((file, record), (i1.clone(), 2));
+                assert!(reason.contains("'#'"), "{}", reason);
+            },
+            other => panic!("Expected a MalformedRecord error, got {:?}", other.map(|_| ()))
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn single_end_annotations_leave_out_the_r2_length() {
+        let dir = test_dir("single_end_annotation");
+        let (i1, annotation_file) = (dir.join("in.fastq"), dir.join("annotation.tsv"));
+        std::fs::write(&i1, format!("@r1\n{}\n+\n{}\n@r2\nACGT\n+\nIIII\n", "A".repeat(40), "I".repeat(40))).unwrap();
+        let args = run_args(&["--i1", i1.to_str().unwrap(), "--annotation_file", annotation_file.to_str().unwrap()]);
+        filter(&args).unwrap();
+        let annotations = std::fs::read_to_string(&annotation_file).unwrap();
+        let rows: Vec<Vec<&str>> = annotations.lines().skip(1).map(|line| line.split('\t').collect()).collect();
+        assert_eq!(rows[0][..3], ["r1", "40", "NA"]);
+        assert_eq!(rows[1][..3], ["r2", "4", "NA"]);
+        assert_eq!(rows[1][4..], ["failed", "length"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stats_only_changed_leaves_out_zero_counters_but_not_options() {
+        let dir = test_dir("stats_only_changed");
+        let (i1, stats_file) = (dir.join("in.fastq"), dir.join("stats.txt"));
+        std::fs::write(&i1, "@r1\nACGT\n+\n####\n@r2\nACGTACGTAC\n+\n##########\n").unwrap();
+        let args = run_args(&[
+            "--i1", i1.to_str().unwrap(), "--threshold", "0", "--max_length", "5", "--stats_only_changed",
+            "--stats_file", stats_file.to_str().unwrap()
+        ]);
+        filter(&args).unwrap();
+        let stats = std::fs::read_to_string(&stats_file).unwrap();
+        let lines: Vec<&str> = stats.lines().collect();
+        for line in &["filter_threshold 0", "max_length 5", "r1_too_long 1", "removed_by_length 1", "q30_bases 0", "q30_fraction 0.0000"] {
+            assert!(lines.contains(line), "{} missing from {}", line, stats);
+        }
+        for name in &["r1_too_short", "empty_read_pairs", "warnings"] {
+            assert!(!lines.iter().any(|line| line.starts_with(name)), "{} in {}", name, stats);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resuming_from_a_checkpoint_matches_an_uninterrupted_run() {
+        let dir = test_dir("resume");
+        for mate in [1, 2] {
+            std::fs::write(dir.join(format!("full_R{}.fastq", mate)), mate_records(mate, 0, 20)).unwrap();
+            std::fs::write(dir.join(format!("part_R{}.fastq", mate)), mate_records(mate, 0, 10)).unwrap();
+        }
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let output = |run: &str, name: &str| path(&format!("{}_{}.fastq{}", run, name, codec::OUTPUT_SUFFIX));
+        let run = |input: &str, outputs: &str, extra: &[&str]| {
+            let (o1, o2, f1, f2) = (output(outputs, "o1"), output(outputs, "o2"), output(outputs, "f1"), output(outputs, "f2"));
+            let (i1, i2) = (path(&format!("{}_R1.fastq", input)), path(&format!("{}_R2.fastq", input)));
+            let mut argv = vec!["--i1", &i1, "--i2", &i2, "--o1", &o1, "--o2", &o2, "--f1", &f1, "--f2", &f2];
+            argv.extend(extra);
+            filter(&run_args(&argv)).unwrap()
+        };
+        let checkpoint_file = path("checkpoint.tsv");
+        run("full", "uninterrupted", &[]);
+        // a run that stops after 10 pairs, with some writes after its last checkpoint
+        run("part", "resumed", &["--checkpoint_file", &checkpoint_file, "--checkpoint_interval", "4"]);
+        for name in ["o1", "o2", "f1", "f2"] {
+            let mut f = OpenOptions::new().append(true).open(output("resumed", name)).unwrap();
+            f.write_all(b"lost after the checkpoint").unwrap();
+        }
+        let stats = run("full", "resumed", &["--checkpoint_file", &checkpoint_file, "--resume"]);
+        assert_eq!(stats.read_pairs_checked, 10);
+        for name in ["o1", "o2", "f1", "f2"] {
+            let (resumed, uninterrupted) = (output("resumed", name), output("uninterrupted", name));
+            assert_eq!(read_output(Path::new(&resumed)), read_output(Path::new(&uninterrupted)), "{}", name);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sorting_by_tile_merges_spilled_runs_in_order() {
+        let dir = test_dir("sort_spill");
+        let mut sorter = PairSorter::new(&SortBy::Tile, 1, dir.clone());
+        sorter.max_bytes = 300;
+        let tiles = ["2101", "1101", "1201", "1101", "2101", "1102", "1201", "1101", "1102", "999"];
+        let mut entry = FastqEntry::new();
+        for (i, tile) in tiles.iter().enumerate() {
+            entry.tile_id = tile.to_string();
+            let (r1, r2) = (format!("@read{}/1\nACGT\n+\nIIII\n", i), format!("@read{}/2\nTTGA\n+\nIIII", i));
+            sorter.add(&entry, r1.as_bytes(), r2.as_bytes()).unwrap();
+        }
+        assert!(sorter.runs.len() > 1);
+
+        let (mut f1, mut f2) = (Vec::new(), Vec::new());
+        sorter.finish(&mut f1, &mut f2).unwrap();
+        let headers = |output: &[u8]| -> Vec<String> {
+            String::from_utf8(output.to_vec()).unwrap().lines().step_by(4).map(String::from).collect()
+        };
+        // numeric tiles sort as numbers, and pairs on the same tile stay in input order
+        let order = [9, 1, 3, 7, 5, 8, 2, 6, 0, 4];
+        assert_eq!(headers(&f1), order.iter().map(|i| format!("@read{}/1", i)).collect::<Vec<_>>());
+        assert_eq!(headers(&f2), order.iter().map(|i| format!("@read{}/2", i)).collect::<Vec<_>>());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interleaved_input_gives_the_same_outputs_as_separate_files() {
+        let dir = test_dir("interleaved");
+        let (r1, r2) = (mate_records(1, 0, 12), mate_records(2, 0, 12));
+        let interleaved_records: String = r1.lines().collect::<Vec<_>>().chunks(4).zip(r2.lines().collect::<Vec<_>>().chunks(4))
+            .map(|(record_1, record_2)| format!("{}\n{}\n", record_1.join("\n"), record_2.join("\n")))
+            .collect();
+        std::fs::write(dir.join("in_R1.fastq"), &r1).unwrap();
+        std::fs::write(dir.join("in_R2.fastq"), &r2).unwrap();
+        std::fs::write(dir.join("interleaved.fastq"), &interleaved_records).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let outputs = |run: &str| ["o1", "o2", "f1", "f2"].iter().map(|name| path(&format!("{}_{}.fastq", run, name))).collect::<Vec<_>>();
+        let run = |inputs: &[String], run: &str| {
+            let outputs = outputs(run);
+            let mut argv: Vec<&str> = vec!["--o1", &outputs[0], "--o2", &outputs[1], "--f1", &outputs[2], "--f2", &outputs[3]];
+            argv.extend(inputs.iter().map(|s| s.as_str()));
+            filter(&run_args(&argv)).unwrap()
+        };
+        let separate = run(&["--i1".to_string(), path("in_R1.fastq"), "--i2".to_string(), path("in_R2.fastq")], "separate");
+        let interleaved = run(&["--i1".to_string(), path("interleaved.fastq"), "--interleaved".to_string()], "interleaved");
+        assert_eq!((interleaved.read_pairs_checked, interleaved.read_pairs_removed), (12, 4));
+        assert_eq!(separate.read_pairs_remaining, interleaved.read_pairs_remaining);
+        for (separate, interleaved) in outputs("separate").iter().zip(outputs("interleaved")) {
+            assert_eq!(std::fs::read(separate).unwrap(), std::fs::read(&interleaved).unwrap(), "{}", interleaved);
+        }
+
+        std::fs::write(dir.join("odd.fastq"), format!("{}{}", interleaved_records, mate_records(1, 12, 1))).unwrap();
+        let odd = path("odd.fastq");
+        assert!(matches!(
+            filter(&run_args(&["--i1", &odd, "--interleaved"])), Err(FiltererError::MalformedRecord { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn single_end_input_writes_only_r1_outputs() {
+        let dir = test_dir("single_end");
+        std::fs::write(dir.join("in.fastq"), mate_records(1, 0, 9)).unwrap();
+        let stats = filter(&run_args(&["--i1", dir.join("in.fastq").to_str().unwrap()])).unwrap();
+        assert_eq!((stats.read_pairs_checked, stats.read_pairs_removed, stats.read_pairs_remaining), (9, 3, 6));
+        assert_eq!((stats.r2_min_len, stats.r2_max_len), (0, 0));
+        let mut files: Vec<String> = std::fs::read_dir(&dir).unwrap()
+            .map(|f| f.unwrap().file_name().to_str().unwrap().to_string())
+            .collect();
+        files.sort();
+        let suffix = codec::OUTPUT_SUFFIX;
+        assert_eq!(files, [
+            "in.fastq".to_string(), format!("in_filtered.fastq{}", suffix), format!("in_filtered_reads.fastq{}", suffix)
+        ]);
+        let kept = String::from_utf8(read_output(&dir.join(format!("in_filtered.fastq{}", suffix)))).unwrap();
+        assert_eq!(kept.lines().count(), 24);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fix_swapped_names_outputs_after_the_input_read_as_each_mate() {
+        let dir = test_dir("fix_swapped");
+        for mate in [1, 2] {
+            let record = format!("@M1:1:FC:1:1101:1000:2000 {}:N:0:A\n{}\n+\n{}\n", mate, "A".repeat(50), "I".repeat(50));
+            std::fs::write(dir.join(format!("sw_R{}.fastq", mate)), record).unwrap();
+        }
+        let (r1, r2) = (dir.join("sw_R1.fastq"), dir.join("sw_R2.fastq"));
+        let args = run_args(&["--i1", r2.to_str().unwrap(), "--i2", r1.to_str().unwrap(), "--fix_swapped"]);
+        let stats = filter(&args).unwrap();
+        assert!(stats.inputs_swapped);
+        for mate in [1, 2] {
+            let output = dir.join(format!("sw_R{}_filtered.fastq{}", mate, codec::OUTPUT_SUFFIX));
+            let header = FastqHandler::first_header(&output).unwrap();
+            assert_eq!(mate_number(&header), Some(mate), "{:?} has {:?}", output, header);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
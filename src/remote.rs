@@ -0,0 +1,37 @@
+use std::io::{self,Read};
+
+
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://") || path.starts_with("s3://")
+}
+
+
+/// s3:// URLs are fetched over HTTPS from the bucket's public endpoint, so only objects readable
+/// without credentials are supported.
+#[cfg(feature="remote")]
+fn resolve_url(url: &str) -> String {
+    match url.strip_prefix("s3://") {
+        Some(s3_path) => {
+            let mut parts = s3_path.splitn(2, '/');
+            let bucket = parts.next().unwrap_or("");
+            let key = parts.next().unwrap_or("");
+            format!("https://{}.s3.amazonaws.com/{}", bucket, key)
+        },
+        None => url.to_string()
+    }
+}
+
+
+#[cfg(feature="remote")]
+pub fn open(url: &str) -> io::Result<Box<dyn Read>> {
+    match ureq::get(&resolve_url(url)).call() {
+        Ok(response) => Ok(Box::new(response.into_reader())),
+        Err(e) => Err(io::Error::other(format!("Could not fetch {}: {}", url, e)))
+    }
+}
+
+
+#[cfg(not(feature="remote"))]
+pub fn open(url: &str) -> io::Result<Box<dyn Read>> {
+    Err(io::Error::other(format!("Could not open {}: built without the 'remote' feature", url)))
+}
@@ -0,0 +1,120 @@
+use std::fs;
+use std::path::{Path,PathBuf};
+use structopt::StructOpt;
+use crate::error::{FiltererError,Result};
+use crate::{Cli,FastqHandler,OutputKind};
+
+
+const GOOD_PAIRS: usize = 70;
+const SHORT_PAIRS: usize = 10;
+const BAD_TILE_PAIRS: usize = 10;
+const LISTED_PAIRS: usize = 10;
+const GOOD_TILE: &str = "1101";
+const BAD_TILE: &str = "2222";
+const READ_LEN: usize = 60;
+const SHORT_LEN: usize = 20;
+
+
+/// Synthetic read pairs of known make-up, and the IDs of those to remove with --remove_reads
+struct Dataset {
+    r1: String,
+    r2: String,
+    listed_ids: String
+}
+
+
+impl Dataset {
+    fn generate() -> Dataset {
+        let mut dataset = Dataset { r1: String::new(), r2: String::new(), listed_ids: String::new() };
+        let bases = b"ACGT";
+        let mut pair = 0;
+        let mut add = |dataset: &mut Dataset, tile: &str, r1_len: usize, listed: bool| {
+            pair += 1;
+            let read_id = format!("SELFTEST:1:FC:1:{}:{}:{}", tile, 1000 + pair, 2000 + pair);
+            let seq: String = (0..READ_LEN).map(|i| bases[(i * 7 + pair) % 4] as char).collect();
+            for (fastq, len, mate) in [(&mut dataset.r1, r1_len, 1), (&mut dataset.r2, READ_LEN, 2)] {
+                fastq.push_str(&format!("@{} {}:N:0:ACGT\n{}\n+\n{}\n", read_id, mate, &seq[..len], "I".repeat(len)));
+            }
+            if listed {
+                dataset.listed_ids.push_str(&format!("{}\n", read_id));
+            }
+        };
+        for _ in 0..GOOD_PAIRS {
+            add(&mut dataset, GOOD_TILE, READ_LEN, false);
+        }
+        for _ in 0..SHORT_PAIRS {
+            add(&mut dataset, GOOD_TILE, SHORT_LEN, false);
+        }
+        for _ in 0..BAD_TILE_PAIRS {
+            add(&mut dataset, BAD_TILE, READ_LEN, false);
+        }
+        for _ in 0..LISTED_PAIRS {
+            add(&mut dataset, GOOD_TILE, READ_LEN, true);
+        }
+        dataset
+    }
+}
+
+
+fn check(failures: &mut Vec<String>, what: &str, expected: usize, observed: usize) {
+    println!("{}\texpected {}\tobserved {}", what, expected, observed);
+    if expected != observed {
+        failures.push(format!("{} was {}, expected {}", what, observed, expected));
+    }
+}
+
+
+fn run_in(dir: &Path) -> Result<Vec<String>> {
+    let dataset = Dataset::generate();
+    let i1 = dir.join("selftest_R1.fastq");
+    let i2 = dir.join("selftest_R2.fastq");
+    let ids_file = dir.join("selftest_ids.txt");
+    fs::write(&i1, &dataset.r1)?;
+    fs::write(&i2, &dataset.r2)?;
+    fs::write(&ids_file, &dataset.listed_ids)?;
+
+    let path_arg = |path: &PathBuf| path.to_string_lossy().into_owned();
+    let argv = vec![
+        "rustq_filterer".to_string(),
+        "--i1".to_string(), path_arg(&i1),
+        "--i2".to_string(), path_arg(&i2),
+        "--threshold".to_string(), (SHORT_LEN + 1).to_string(),
+        "--remove_tiles".to_string(), BAD_TILE.to_string(),
+        "--remove_reads".to_string(), path_arg(&ids_file),
+        "--stats_file".to_string(), path_arg(&dir.join("selftest_stats.txt"))
+    ];
+    let args = Cli::from_iter_safe(argv).map_err(|e| FiltererError::Config(e.message))?;
+    let stats = crate::filter(&args)?;
+
+    let removed = SHORT_PAIRS + BAD_TILE_PAIRS + LISTED_PAIRS;
+    let total = GOOD_PAIRS + removed;
+    let mut failures = Vec::new();
+    check(&mut failures, "read_pairs_checked", total, stats.read_pairs_checked as usize);
+    check(&mut failures, "read_pairs_removed", removed, stats.read_pairs_removed as usize);
+    check(&mut failures, "read_pairs_remaining", GOOD_PAIRS, stats.read_pairs_remaining as usize);
+    check(&mut failures, "r1_too_short", SHORT_PAIRS, stats.r1_too_short as usize);
+    for mate in [1, 2] {
+        let kept = FastqHandler::count_records(&args.output_path(mate, OutputKind::Kept, false), None, false)? as usize;
+        let rejected = FastqHandler::count_records(&args.output_path(mate, OutputKind::Rejected, false), None, false)? as usize;
+        check(&mut failures, &format!("r{}_records_kept", mate), GOOD_PAIRS, kept);
+        check(&mut failures, &format!("r{}_records_rejected", mate), removed, rejected);
+    }
+    Ok(failures)
+}
+
+
+/// Filter a synthetic dataset with known numbers of short reads, reads on a removed tile and reads
+/// in a removal list, and check that the run keeps and rejects exactly the expected pairs
+pub fn run(tmp_dir: &Path) -> Result<()> {
+    let dir = tmp_dir.join(format!("rustq_filterer_selftest_{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let outcome = run_in(&dir);
+    fs::remove_dir_all(&dir)?;
+    let failures = outcome?;
+    if failures.is_empty() {
+        println!("Self-test passed");
+        Ok(())
+    } else {
+        Err(FiltererError::SelfTest(failures.join("; ")))
+    }
+}